@@ -0,0 +1,305 @@
+//! Checking bookmarks for broken links, by requesting each bookmarked page
+//! and recording how its server replied
+use {
+    crate::feeds::{fetch, known_hosts::Trust},
+    chrono::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        io::{self, Read, Write},
+        sync::{mpsc, Mutex},
+        thread,
+    },
+    url::Url,
+};
+
+/// How many bookmarks are checked at the same time
+pub const CHECK_THREADS: usize = 8;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum LinkStatus {
+    /// The page was found, or the server asked for input
+    Ok,
+    /// The page has moved to the target of the check
+    Redirect,
+    /// The server replied with a failure, or asked for a client certificate
+    Failed,
+    /// The host could not be resolved
+    Dns,
+    /// The server could not be reached
+    Connection,
+    /// The TLS handshake failed
+    Tls,
+    /// Only gemini links which are not keyword templates are checked
+    Unsupported,
+}
+
+/// The result of checking a bookmark
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LinkCheck {
+    pub checked: DateTime<Local>,
+    pub status: LinkStatus,
+    /// The reply from the server, or why the check failed
+    #[serde(default)]
+    pub message: Option<String>,
+    /// The absolute url which a redirect leads to
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Whether the redirect is permanent, so that the bookmark can be
+    /// updated to follow it
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+impl LinkCheck {
+    fn new(status: LinkStatus, message: Option<String>) -> Self {
+        Self {
+            checked: Local::now(),
+            status,
+            message,
+            target: None,
+            permanent: false,
+        }
+    }
+
+    /// Whether the bookmark has permanently moved to `target`
+    #[must_use]
+    pub fn can_follow(&self) -> bool {
+        self.permanent && self.target.is_some()
+    }
+
+    /// Whether the link no longer works, as opposed to having moved
+    #[must_use]
+    pub fn is_broken(&self) -> bool {
+        matches!(
+            self.status,
+            LinkStatus::Failed | LinkStatus::Dns | LinkStatus::Connection | LinkStatus::Tls
+        )
+    }
+
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let status = match self.status {
+            LinkStatus::Ok => "Ok",
+            LinkStatus::Redirect => "Redirected",
+            LinkStatus::Failed => "Failed",
+            LinkStatus::Dns => "Host not found",
+            LinkStatus::Connection => "Unable to connect",
+            LinkStatus::Tls => "TLS error",
+            LinkStatus::Unsupported => "Not checked",
+        };
+        match &self.message {
+            Some(message) => format!("{status}: {message}"),
+            None => String::from(status),
+        }
+    }
+}
+
+/// Checks the link to `url` over a new connection, checking the server
+/// against the known hosts in `trust`
+#[must_use]
+pub fn check(url: &str, trust: &Trust) -> LinkCheck {
+    // The url of a keyword bookmark is only complete once a query is filled in
+    if url.contains("%s") {
+        return LinkCheck::new(
+            LinkStatus::Unsupported,
+            Some(String::from("Keyword template")),
+        );
+    }
+    let url = match Url::parse(url) {
+        Ok(u) => u,
+        Err(e) => return LinkCheck::new(LinkStatus::Failed, Some(e.to_string())),
+    };
+    if url.scheme() != "gemini" {
+        return LinkCheck::new(LinkStatus::Unsupported, None);
+    }
+    let addr = match fetch::resolve(&url) {
+        Ok(a) => a,
+        Err(e) => return LinkCheck::new(LinkStatus::Dns, Some(e.to_string())),
+    };
+    let stream = match fetch::connect(&addr) {
+        Ok(s) => s,
+        Err(e) => return LinkCheck::new(LinkStatus::Connection, Some(e.to_string())),
+    };
+    match fetch::tls(&url, stream, trust) {
+        Ok(stream) => check_stream(&url, stream),
+        Err(e) => LinkCheck::new(LinkStatus::Tls, Some(e.to_string())),
+    }
+}
+
+/// Requests `url` over `stream` and sorts out what the reply means for the
+/// bookmark. This works over any stream, so it does not depend on a TLS
+/// connection.
+pub fn check_stream<S: Read + Write>(url: &Url, stream: S) -> LinkCheck {
+    let (status, meta) = match fetch::status(stream, url) {
+        Ok(reply) => reply,
+        Err(e) if e.is::<io::Error>() => {
+            return LinkCheck::new(LinkStatus::Connection, Some(e.to_string()))
+        }
+        Err(e) => return LinkCheck::new(LinkStatus::Failed, Some(e.to_string())),
+    };
+    match status / 10 {
+        1 | 2 => LinkCheck::new(LinkStatus::Ok, None),
+        3 => match url.join(&meta) {
+            Ok(target) => LinkCheck {
+                target: Some(target.to_string()),
+                permanent: status == 31,
+                ..LinkCheck::new(LinkStatus::Redirect, Some(format!("{status} {meta}")))
+            },
+            Err(_) => LinkCheck::new(
+                LinkStatus::Failed,
+                Some(format!("Invalid redirect: {status} {meta}")),
+            ),
+        },
+        _ => LinkCheck::new(LinkStatus::Failed, Some(format!("{status} {meta}"))),
+    }
+}
+
+/// Runs `check` on each of `urls`, at most `threads` at a time. `done` is
+/// called on the current thread with the result for each url as it comes in.
+/// # Panics
+/// Panics if a thread running `check` panics
+pub fn check_all<C, D>(urls: Vec<String>, threads: usize, check: C, mut done: D)
+where
+    C: Fn(&str) -> LinkCheck + Sync,
+    D: FnMut(String, LinkCheck),
+{
+    let queue = Mutex::new(urls.into_iter());
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let (queue, check) = (&queue, &check);
+            let _handle = scope.spawn(move || loop {
+                let url = match queue.lock().unwrap().next() {
+                    Some(url) => url,
+                    None => break,
+                };
+                let result = check(&url);
+                if sender.send((url, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        for (url, result) in receiver {
+            done(url, result);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::feeds::mock::MockStream,
+        std::{
+            collections::HashSet,
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        },
+    };
+
+    fn check_reply(reply: &str) -> LinkCheck {
+        let url = Url::parse("gemini://example.org/old/page.gmi").unwrap();
+        check_stream(&url, MockStream::new(reply))
+    }
+
+    #[test]
+    fn input_counts_as_ok() {
+        assert_eq!(check_reply("20 text/gemini\r\n").status, LinkStatus::Ok);
+        assert_eq!(check_reply("10 Search\r\n").status, LinkStatus::Ok);
+        assert_eq!(check_reply("11 Password\r\n").status, LinkStatus::Ok);
+    }
+
+    #[test]
+    fn redirect_target() {
+        let result = check_reply("31 ../new/page.gmi\r\n");
+        assert_eq!(result.status, LinkStatus::Redirect);
+        assert_eq!(result.message.as_deref(), Some("31 ../new/page.gmi"));
+        assert_eq!(
+            result.target.as_deref(),
+            Some("gemini://example.org/new/page.gmi")
+        );
+        assert!(!result.is_broken());
+        assert!(result.can_follow());
+        let result = check_reply("30 gemini://example.net/\r\n");
+        assert_eq!(result.target.as_deref(), Some("gemini://example.net/"));
+        assert!(!result.can_follow());
+    }
+
+    #[test]
+    fn failures_are_broken() {
+        for reply in ["51 Not found\r\n", "60 Certificate required\r\n"] {
+            let result = check_reply(reply);
+            assert_eq!(result.status, LinkStatus::Failed);
+            assert_eq!(result.message.as_deref(), Some(reply.trim_end()));
+            assert!(result.is_broken());
+        }
+        // Replies which can not be understood are failures of the server,
+        // not of the connection
+        let result = check_reply(&format!("20 {}\r\n", "x".repeat(2048)));
+        assert_eq!(result.status, LinkStatus::Failed);
+        assert_eq!(check_reply("").status, LinkStatus::Failed);
+    }
+
+    #[test]
+    fn connection_reset() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let stream = MockStream::failing(io::ErrorKind::ConnectionReset);
+        let result = check_stream(&url, stream);
+        assert_eq!(result.status, LinkStatus::Connection);
+        assert!(result.is_broken());
+    }
+
+    #[test]
+    fn unsupported() {
+        let check = |url| check(url, &Trust::Saved);
+        let result = check("gemini://example.org/search?%s");
+        assert_eq!(result.status, LinkStatus::Unsupported);
+        assert!(!result.is_broken());
+        assert_eq!(
+            check("https://example.org/").status,
+            LinkStatus::Unsupported
+        );
+        assert_eq!(check("not a url").status, LinkStatus::Failed);
+    }
+
+    #[test]
+    fn check_each_once() {
+        let urls: Vec<String> = (0..50)
+            .map(|i| format!("gemini://{i}.example.org/"))
+            .collect();
+        let mut seen = HashSet::new();
+        check_all(
+            urls.clone(),
+            CHECK_THREADS,
+            |_| LinkCheck::new(LinkStatus::Ok, None),
+            |url, _| assert!(seen.insert(url)),
+        );
+        assert_eq!(seen, urls.into_iter().collect());
+    }
+
+    #[test]
+    fn thread_limit() {
+        for threads in [0, 1, 3] {
+            let running = AtomicUsize::new(0);
+            let most = AtomicUsize::new(0);
+            let urls: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+            let mut done = 0;
+            check_all(
+                urls,
+                threads,
+                |_| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    let _ = most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    let _ = running.fetch_sub(1, Ordering::SeqCst);
+                    LinkCheck::new(LinkStatus::Ok, None)
+                },
+                |_, _| done += 1,
+            );
+            assert_eq!(done, 20);
+            assert!(most.load(Ordering::SeqCst) <= threads.max(1));
+        }
+    }
+}
@@ -0,0 +1,648 @@
+//! Converting bookmarks to and from the formats used by other browsers. The
+//! parsers here are deliberately forgiving, skipping anything which they do
+//! not understand rather than failing the whole import.
+use {
+    super::{child_folders, folder_paths, normalize_folder, normalize_keyword, Bookmark},
+    chrono::prelude::*,
+    std::{collections::HashMap, fmt::Write, path::Path},
+};
+
+/// Tags which Lagrange uses internally to mark special bookmarks
+const LAGRANGE_TAGS: [&str; 5] = [
+    "homepage",
+    "linksplit",
+    "remotesource",
+    "subscribed",
+    "usericon",
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A gemtext page of links, grouped under a heading for their first tag
+    Gemtext,
+    /// Lagrange's bookmarks.ini, or the bookmarks.txt of older versions
+    Lagrange,
+    /// The XBEL bookmarks.xml file written by Amfora
+    Amfora,
+    /// Kristall's favourites.ini
+    Kristall,
+    /// The bookmarks.html format understood by most web browsers
+    Netscape,
+}
+
+impl Format {
+    /// Guesses the format of a file from its name
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_str()?.to_lowercase();
+        match path.extension()?.to_str()? {
+            "gmi" | "gemini" => Some(Self::Gemtext),
+            "ini" if stem.contains("favourite") => Some(Self::Kristall),
+            "ini" | "txt" => Some(Self::Lagrange),
+            "xml" | "xbel" => Some(Self::Amfora),
+            "html" | "htm" => Some(Self::Netscape),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a file from its name, then checks the guess
+    /// against its contents, as both Lagrange and Kristall use .ini files
+    #[must_use]
+    pub fn detect(path: &Path, text: &str) -> Option<Self> {
+        match Self::from_path(path)? {
+            Self::Lagrange | Self::Kristall if text.contains("\\url=") => Some(Self::Kristall),
+            Self::Lagrange | Self::Kristall => Some(Self::Lagrange),
+            f => Some(f),
+        }
+    }
+}
+
+fn bookmark(
+    url: &str,
+    name: Option<&str>,
+    description: Option<&str>,
+    tags: Vec<String>,
+    folder: Option<String>,
+) -> Bookmark {
+    let name = name.map(str::trim).filter(|n| !n.is_empty()).unwrap_or(url);
+    Bookmark {
+        name: String::from(name),
+        description: description
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(String::from),
+        url: String::from(url.trim()),
+        tags,
+        folder,
+        created: None,
+        visited: None,
+        keyword: None,
+        check: None,
+    }
+}
+
+/// Reads a date written as seconds since the Unix epoch, which may have a
+/// fractional part as in Lagrange's bookmarks
+fn timestamp(text: &str) -> Option<DateTime<Local>> {
+    let secs = text.trim().split('.').next()?.parse().ok()?;
+    Local.timestamp_opt(secs, 0).single()
+}
+
+/// Joins the names of nested folders into a folder path. Lists which are not
+/// folders, such as the outermost list of a Netscape bookmarks file, are
+/// recorded as `None` and skipped.
+fn folder_path(folders: &[Option<String>]) -> Option<String> {
+    let path = folders
+        .iter()
+        .flatten()
+        .map(|f| f.replace('/', "-"))
+        .collect::<Vec<_>>()
+        .join("/");
+    normalize_folder(&path)
+}
+
+/// The folders directly inside of `parent`
+fn subfolders(bookmarks: &[&Bookmark], parent: Option<&str>) -> Vec<String> {
+    child_folders(bookmarks.iter().filter_map(|b| b.folder.as_deref()), parent)
+}
+
+/// The last component of a folder path
+fn folder_name(folder: &str) -> &str {
+    folder.rsplit('/').next().unwrap_or(folder)
+}
+
+/// Turns a folder or heading name into a tag, as tags may not contain spaces
+fn to_tag(name: &str) -> Option<String> {
+    let tag = name.split_whitespace().collect::<Vec<_>>().join("_");
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_markup(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Escapes a string for the double quoted values in Lagrange's ini file
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(t) => t.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => String::from(text),
+    }
+}
+
+/// Finds `name="value"` within a markup tag, ignoring the case of `name`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let start = lower.find(&format!(" {name}=\""))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(unescape_markup(&tag[start..end]))
+}
+
+/// Returns the text between the first `open` and the following `close`,
+/// ignoring case
+fn between<'a>(text: &'a str, open: &str, close: &str) -> Option<&'a str> {
+    let lower = text.to_ascii_lowercase();
+    let start = lower.find(open)? + open.len();
+    let end = start + lower[start..].find(close)?;
+    Some(&text[start..end])
+}
+
+/// Bookmarks sorted by name, so that exports are the same each time
+fn sorted<'a>(bookmarks: impl Iterator<Item = &'a Bookmark>) -> Vec<&'a Bookmark> {
+    let mut bookmarks: Vec<&Bookmark> = bookmarks.collect();
+    bookmarks.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.url.cmp(&b.url)));
+    bookmarks
+}
+
+pub(super) fn export<'a>(bookmarks: impl Iterator<Item = &'a Bookmark>, format: Format) -> String {
+    let bookmarks = sorted(bookmarks);
+    match format {
+        Format::Gemtext => export_gemtext(&bookmarks),
+        Format::Lagrange => export_lagrange(&bookmarks),
+        Format::Amfora => export_xbel(&bookmarks),
+        Format::Kristall => export_kristall(&bookmarks),
+        Format::Netscape => export_netscape(&bookmarks),
+    }
+}
+
+fn export_gemtext(bookmarks: &[&Bookmark]) -> String {
+    let mut out = String::from("# Bookmarks\n");
+    let mut groups: Vec<(Option<&String>, Vec<&Bookmark>)> = vec![];
+    for bm in bookmarks {
+        let tag = bm.tags.first();
+        match groups.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, group)) => group.push(bm),
+            None => groups.push((tag, vec![bm])),
+        }
+    }
+    groups.sort_by_key(|(tag, _)| *tag);
+    for (tag, group) in groups {
+        if let Some(tag) = tag {
+            let _ = writeln!(out, "\n## {tag}");
+        }
+        out.push('\n');
+        for bm in group {
+            let _ = writeln!(out, "=> {} {}", bm.url, bm.name);
+        }
+    }
+    out
+}
+
+/// Folders are written as sections without a url, which the sections inside
+/// them refer to as their parent
+fn export_lagrange(bookmarks: &[&Bookmark]) -> String {
+    let mut out = String::new();
+    let folders = folder_paths(bookmarks.iter().filter_map(|b| b.folder.as_deref()));
+    let id = |folder: &str| folders.iter().position(|f| f == folder).map(|i| i + 1);
+    let parent = |folder: Option<&str>| match folder.and_then(id) {
+        Some(id) => format!("parent = {id}\n"),
+        None => String::new(),
+    };
+    for (n, folder) in folders.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "[{}]\ntitle = {}\n{}",
+            n + 1,
+            quote(folder_name(folder)),
+            parent(folder.rsplit_once('/').map(|(p, _)| p)),
+        );
+    }
+    for (n, bm) in bookmarks.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "[{}]\nurl = {}\ntitle = {}\ntags = {}\n{}{}",
+            folders.len() + n + 1,
+            quote(&bm.url),
+            quote(&bm.name),
+            quote(&bm.tags.join(" ")),
+            bm.created
+                .map(|c| format!("created = {}\n", c.timestamp()))
+                .unwrap_or_default(),
+            parent(bm.folder.as_deref()),
+        );
+    }
+    out
+}
+
+fn export_xbel(bookmarks: &[&Bookmark]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <!DOCTYPE xbel PUBLIC \"+//IDN python.org//DTD XML Bookmark Exchange Language 1.0//EN//XML\" \
+        \"http://www.python.org/topics/xml/dtds/xbel-1.0.dtd\">\n\
+        <xbel version=\"1.0\">\n",
+    );
+    xbel_folder(bookmarks, None, 1, &mut out);
+    out.push_str("</xbel>\n");
+    out
+}
+
+fn xbel_folder(bookmarks: &[&Bookmark], folder: Option<&str>, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for sub in subfolders(bookmarks, folder) {
+        let _ = writeln!(
+            out,
+            "{indent}<folder>\n{indent}  <title>{}</title>",
+            escape_markup(folder_name(&sub)),
+        );
+        xbel_folder(bookmarks, Some(&sub), depth + 1, out);
+        let _ = writeln!(out, "{indent}</folder>");
+    }
+    for bm in bookmarks.iter().filter(|b| b.folder.as_deref() == folder) {
+        let _ = writeln!(
+            out,
+            "{indent}<bookmark href=\"{}\">\n{indent}  <title>{}</title>",
+            escape_markup(&bm.url),
+            escape_markup(&bm.name),
+        );
+        if let Some(desc) = &bm.description {
+            let _ = writeln!(out, "{indent}  <desc>{}</desc>", escape_markup(desc));
+        }
+        let _ = writeln!(out, "{indent}</bookmark>");
+    }
+}
+
+/// Kristall groups its favourites, and each bookmark is placed in the group
+/// named by its first tag
+fn export_kristall(bookmarks: &[&Bookmark]) -> String {
+    let mut groups: Vec<(&str, Vec<&Bookmark>)> = vec![];
+    for bm in bookmarks {
+        let group = bm.tags.first().map_or("Unsorted", String::as_str);
+        match groups.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, group)) => group.push(bm),
+            None => groups.push((group, vec![bm])),
+        }
+    }
+    let mut out = String::from("[groups]\n");
+    for (g, (name, group)) in groups.iter().enumerate() {
+        let g = g + 1;
+        let _ = writeln!(out, "{g}\\name={name}");
+        for (f, bm) in group.iter().enumerate() {
+            let f = f + 1;
+            let _ = writeln!(
+                out,
+                "{g}\\favourites\\{f}\\title={}\n{g}\\favourites\\{f}\\url={}",
+                bm.name, bm.url,
+            );
+        }
+        let _ = writeln!(out, "{g}\\favourites\\size={}", group.len());
+    }
+    let _ = writeln!(out, "size={}", groups.len());
+    out
+}
+
+fn export_netscape(bookmarks: &[&Bookmark]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+        <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+        <TITLE>Bookmarks</TITLE>\n<H1>Bookmarks</H1>\n<DL><p>\n",
+    );
+    netscape_folder(bookmarks, None, 1, &mut out);
+    out.push_str("</DL><p>\n");
+    out
+}
+
+fn netscape_folder(bookmarks: &[&Bookmark], folder: Option<&str>, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for sub in subfolders(bookmarks, folder) {
+        let _ = writeln!(
+            out,
+            "{indent}<DT><H3>{}</H3>\n{indent}<DL><p>",
+            escape_markup(folder_name(&sub)),
+        );
+        netscape_folder(bookmarks, Some(&sub), depth + 1, out);
+        let _ = writeln!(out, "{indent}</DL><p>");
+    }
+    for bm in bookmarks.iter().filter(|b| b.folder.as_deref() == folder) {
+        let _ = write!(out, "{indent}<DT><A HREF=\"{}\"", escape_markup(&bm.url));
+        if let Some(created) = bm.created {
+            let _ = write!(out, " ADD_DATE=\"{}\"", created.timestamp());
+        }
+        if let Some(visited) = bm.visited {
+            let _ = write!(out, " LAST_VISIT=\"{}\"", visited.timestamp());
+        }
+        if let Some(keyword) = &bm.keyword {
+            let _ = write!(out, " SHORTCUTURL=\"{}\"", escape_markup(keyword));
+        }
+        if !bm.tags.is_empty() {
+            let _ = write!(out, " TAGS=\"{}\"", escape_markup(&bm.tags.join(",")));
+        }
+        let _ = writeln!(out, ">{}</A>", escape_markup(&bm.name));
+        if let Some(desc) = &bm.description {
+            let _ = writeln!(out, "{indent}<DD>{}", escape_markup(desc));
+        }
+    }
+}
+
+pub(super) fn import(text: &str, format: Format) -> Vec<Bookmark> {
+    match format {
+        Format::Gemtext => import_gemtext(text),
+        Format::Lagrange if text.lines().any(|l| l.trim_start().starts_with('[')) => {
+            import_lagrange(text)
+        }
+        Format::Lagrange => import_lagrange_txt(text),
+        Format::Amfora => import_xbel(text),
+        Format::Kristall => import_kristall(text),
+        Format::Netscape => import_netscape(text),
+    }
+}
+
+/// Every link becomes a bookmark, tagged with the heading above it
+fn import_gemtext(text: &str) -> Vec<Bookmark> {
+    let mut tag = None;
+    text.lines()
+        .filter_map(|line| {
+            if let Some(heading) = line.strip_prefix("##") {
+                tag = to_tag(heading.trim_start_matches('#'));
+                return None;
+            }
+            let link = line.strip_prefix("=>")?.trim();
+            let (url, name) = match link.split_once(char::is_whitespace) {
+                Some((url, name)) => (url, Some(name)),
+                None => (link, None),
+            };
+            Some(bookmark(
+                url,
+                name,
+                None,
+                tag.iter().cloned().collect(),
+                None,
+            ))
+        })
+        .collect()
+}
+
+fn import_lagrange(text: &str) -> Vec<Bookmark> {
+    let mut sections: Vec<(&str, HashMap<&str, String>)> = vec![];
+    for line in text.lines().map(str::trim) {
+        if let Some(id) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((id, HashMap::new()));
+        } else if let (Some((_, section)), Some((key, value))) =
+            (sections.last_mut(), line.split_once('='))
+        {
+            let _old = section.insert(key.trim(), unquote(value.trim()));
+        }
+    }
+    // Sections without a url are folders, which are referred to by the id of
+    // their section
+    let folders: HashMap<&str, (&str, Option<&str>)> = sections
+        .iter()
+        .filter(|(_, section)| !section.contains_key("url"))
+        .filter_map(|(id, section)| {
+            let title = section.get("title")?;
+            Some((
+                *id,
+                (title.as_str(), section.get("parent").map(String::as_str)),
+            ))
+        })
+        .collect();
+    sections
+        .iter()
+        .filter_map(|(_, section)| {
+            let url = section.get("url")?;
+            let tags = section
+                .get("tags")
+                .map(|tags| {
+                    tags.split_whitespace()
+                        .filter(|t| !t.starts_with('.') && !LAGRANGE_TAGS.contains(t))
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(Bookmark {
+                created: section.get("created").and_then(|c| timestamp(c)),
+                ..bookmark(
+                    url,
+                    section.get("title").map(String::as_str),
+                    section.get("notes").map(String::as_str),
+                    tags,
+                    lagrange_folder(&folders, section.get("parent").map(String::as_str)),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Follows the chain of parents from a section of Lagrange's bookmarks.ini to
+/// build the path of the folder which contains it
+fn lagrange_folder<'a>(
+    folders: &HashMap<&'a str, (&'a str, Option<&'a str>)>,
+    mut parent: Option<&'a str>,
+) -> Option<String> {
+    let mut path = vec![];
+    // The limit guards against a folder which is its own ancestor
+    while let Some((title, grandparent)) = parent.and_then(|p| folders.get(p)) {
+        if path.len() > 32 {
+            break;
+        }
+        path.insert(0, Some(String::from(*title)));
+        parent = *grandparent;
+    }
+    folder_path(&path)
+}
+
+/// Older versions of Lagrange wrote three lines for each bookmark: the icon
+/// and creation time followed by the url, then the title, then the tags
+fn import_lagrange_txt(text: &str) -> Vec<Bookmark> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines
+        .chunks(3)
+        .filter_map(|chunk| {
+            let url = chunk.first()?.split_whitespace().last()?;
+            if !url.contains(':') {
+                return None;
+            }
+            let tags = chunk
+                .get(2)
+                .map(|tags| {
+                    tags.split_whitespace()
+                        .filter(|t| !LAGRANGE_TAGS.contains(t))
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(bookmark(url, chunk.get(1).copied(), None, tags, None))
+        })
+        .collect()
+}
+
+fn import_xbel(text: &str) -> Vec<Bookmark> {
+    let lower = text.to_ascii_lowercase();
+    let mut bookmarks = vec![];
+    let mut folders: Vec<Option<String>> = vec![];
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find('<').map(|i| pos + i) {
+        let tag_end = match lower[start..].find('>') {
+            Some(i) => start + i,
+            None => break,
+        };
+        let tag = &lower[start..tag_end];
+        pos = tag_end;
+        if tag.starts_with("<folder") {
+            // The folder's title is the first element inside of it
+            let title = lower[tag_end..]
+                .trim_start_matches('>')
+                .trim_start()
+                .starts_with("<title>")
+                .then(|| between(&text[tag_end..], "<title>", "</title>"))
+                .flatten()
+                .map(unescape_markup);
+            folders.push(title);
+        } else if tag.starts_with("</folder") {
+            let _folder = folders.pop();
+        } else if tag.starts_with("<bookmark ") {
+            let end = lower[tag_end..]
+                .find("</bookmark>")
+                .map_or(text.len(), |i| tag_end + i);
+            let body = &text[tag_end + 1..end];
+            if let Some(url) = attribute(&text[start..tag_end], "href") {
+                bookmarks.push(bookmark(
+                    &url,
+                    between(body, "<title>", "</title>")
+                        .map(unescape_markup)
+                        .as_deref(),
+                    between(body, "<desc>", "</desc>")
+                        .map(unescape_markup)
+                        .as_deref(),
+                    vec![],
+                    folder_path(&folders),
+                ));
+            }
+            pos = end;
+        }
+    }
+    bookmarks
+}
+
+/// The group and index of one of Kristall's favourites
+type FavouriteId<'a> = (&'a str, &'a str);
+
+/// Kristall stores its favourites in groups, using the ini format written by
+/// Qt's `QSettings` arrays, with keys such as `1\favourites\2\url`. Each
+/// group name becomes a tag.
+fn import_kristall(text: &str) -> Vec<Bookmark> {
+    let mut groups: HashMap<&str, String> = HashMap::new();
+    let mut favourites: Vec<(FavouriteId, HashMap<&str, String>)> = vec![];
+    for line in text.lines().map(str::trim) {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key, unquote(value)),
+            None => continue,
+        };
+        match key.split('\\').collect::<Vec<_>>()[..] {
+            [group, "name"] => {
+                let _old = groups.insert(group, value);
+            }
+            [group, "favourites", fav, field] => {
+                match favourites.iter_mut().find(|(id, _)| *id == (group, fav)) {
+                    Some((_, fields)) => {
+                        let _old = fields.insert(field, value);
+                    }
+                    None => favourites.push(((group, fav), HashMap::from([(field, value)]))),
+                }
+            }
+            _ => {}
+        }
+    }
+    favourites
+        .iter()
+        .filter_map(|((group, _), fields)| {
+            let tags = groups
+                .get(group)
+                .filter(|name| !name.eq_ignore_ascii_case("unsorted"))
+                .and_then(|name| to_tag(name))
+                .into_iter()
+                .collect();
+            Some(bookmark(
+                fields.get("url")?,
+                fields.get("title").map(String::as_str),
+                None,
+                tags,
+                None,
+            ))
+        })
+        .collect()
+}
+
+fn import_netscape(text: &str) -> Vec<Bookmark> {
+    let lower = text.to_ascii_lowercase();
+    let mut bookmarks = vec![];
+    // Each folder is a heading followed by a list
+    let mut folders: Vec<Option<String>> = vec![];
+    let mut heading = None;
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find('<').map(|i| pos + i) {
+        let tag_end = match lower[start..].find('>') {
+            Some(i) => start + i,
+            None => break,
+        };
+        let tag = &lower[start..tag_end];
+        pos = tag_end;
+        if tag.starts_with("<h3") {
+            let end = lower[tag_end..]
+                .find("</h3>")
+                .map_or(text.len(), |i| tag_end + i);
+            heading = Some(unescape_markup(&text[tag_end + 1..end]));
+            pos = end;
+        } else if tag.starts_with("<dl") {
+            folders.push(heading.take());
+        } else if tag.starts_with("</dl") {
+            let _folder = folders.pop();
+        } else if tag.starts_with("<a ") {
+            let end = lower[tag_end..]
+                .find("</a>")
+                .map_or(text.len(), |i| tag_end + i);
+            let tag = &text[start..tag_end];
+            pos = end;
+            let url = match attribute(tag, "href") {
+                Some(url) if url.contains(':') && !url.starts_with("javascript:") => url,
+                _ => continue,
+            };
+            let name = unescape_markup(&text[tag_end + 1..end]);
+            // A description follows the link in a <DD> element
+            let rest = &lower[end..];
+            let description = rest
+                .trim_start_matches("</a>")
+                .trim_start()
+                .starts_with("<dd>")
+                .then(|| {
+                    let start = end + rest.find("<dd>").unwrap_or(0) + 4;
+                    let len = text[start..].find('<').unwrap_or(text.len() - start);
+                    unescape_markup(&text[start..start + len])
+                });
+            let tags = attribute(tag, "tags")
+                .map(|tags| tags.split(',').filter_map(to_tag).collect())
+                .unwrap_or_default();
+            bookmarks.push(Bookmark {
+                created: attribute(tag, "add_date").and_then(|d| timestamp(&d)),
+                visited: attribute(tag, "last_visit").and_then(|d| timestamp(&d)),
+                keyword: attribute(tag, "shortcuturl").and_then(|k| normalize_keyword(&k)),
+                ..bookmark(
+                    &url,
+                    Some(&name),
+                    description.as_deref(),
+                    tags,
+                    folder_path(&folders),
+                )
+            });
+        }
+    }
+    bookmarks
+}
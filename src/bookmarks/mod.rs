@@ -1,8 +1,27 @@
 #![warn(clippy::all, clippy::pedantic)]
 use {
-    crate::gui::tab::BookmarkEditor,
+    crate::{
+        config::{BookmarkSort, BookmarkView},
+        gui::tab::BookmarkEditor,
+        storage,
+    },
+    chrono::prelude::*,
     serde::{Deserialize, Serialize},
-    std::{collections::HashMap, error::Error, fmt::Write, path::PathBuf},
+    std::{
+        collections::{BTreeSet, HashMap},
+        error::Error,
+        fmt::Write,
+        path::PathBuf,
+        sync::Mutex,
+    },
+};
+
+mod check;
+mod format;
+
+pub use {
+    check::{check, check_all, check_stream, LinkCheck, LinkStatus, CHECK_THREADS},
+    format::Format,
 };
 
 #[must_use]
@@ -20,12 +39,129 @@ pub fn get_bookmarks_file() -> PathBuf {
     bmarks
 }
 
+/// Tidies a folder path such as `" gemini//tech/ "` into `"gemini/tech"`.
+/// An empty path is the top level, which is represented as `None`.
+#[must_use]
+pub fn normalize_folder(folder: &str) -> Option<String> {
+    let folder = folder
+        .split('/')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if folder.is_empty() {
+        None
+    } else {
+        Some(folder)
+    }
+}
+
+/// Returns each of `folders` along with all of their parents, sorted
+fn folder_paths<'a>(folders: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut paths = BTreeSet::new();
+    for folder in folders {
+        let mut path = String::new();
+        for name in folder.split('/') {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(name);
+            paths.insert(path.clone());
+        }
+    }
+    paths.into_iter().collect()
+}
+
+/// Returns the folders directly inside of `parent` which lead to any of
+/// `folders`, sorted
+fn child_folders<'a>(folders: impl Iterator<Item = &'a str>, parent: Option<&str>) -> Vec<String> {
+    let mut children = BTreeSet::new();
+    for folder in folders {
+        let rest = match parent {
+            Some(p) => folder.strip_prefix(p).and_then(|r| r.strip_prefix('/')),
+            None => Some(folder),
+        };
+        if let Some(name) = rest.and_then(|r| r.split('/').next()) {
+            children.insert(match parent {
+                Some(p) => format!("{p}/{name}"),
+                None => String::from(name),
+            });
+        }
+    }
+    children.into_iter().collect()
+}
+
+/// Returns the eva:// url of the page for `folder`
+fn folder_url(folder: &str) -> String {
+    let path = folder
+        .split('/')
+        .map(|s| urlencoding::encode(s).into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("eva://bookmarks/folder/{path}")
+}
+
+/// Tidies a keyword, which may not contain whitespace as everything after
+/// the first word typed in the address bar is the query
+#[must_use]
+pub fn normalize_keyword(keyword: &str) -> Option<String> {
+    keyword.split_whitespace().next().map(String::from)
+}
+
+/// Returns the eva:// url of the page for `tag`
+fn tag_url(tag: &str) -> String {
+    format!("eva://bookmarks/tags/{}", urlencoding::encode(tag))
+}
+
+/// Returns `bookmarks` in the order chosen in the preferences. Bookmarks
+/// which have never been visited, or which were added before the date was
+/// recorded, come last when sorting by date.
+fn sorted<'a>(
+    bookmarks: impl Iterator<Item = &'a Bookmark>,
+    sort: BookmarkSort,
+) -> Vec<&'a Bookmark> {
+    let mut bookmarks: Vec<&Bookmark> = bookmarks.collect();
+    let by_name = |a: &Bookmark, b: &Bookmark| {
+        a.name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then_with(|| a.url.cmp(&b.url))
+    };
+    match sort {
+        BookmarkSort::Name => bookmarks.sort_by(|a, b| by_name(a, b)),
+        BookmarkSort::Added => {
+            bookmarks.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| by_name(a, b)));
+        }
+        BookmarkSort::Visited => {
+            bookmarks.sort_by(|a, b| b.visited.cmp(&a.visited).then_with(|| by_name(a, b)));
+        }
+    }
+    bookmarks
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Bookmark {
     name: String,
     description: Option<String>,
     url: String,
     tags: Vec<String>,
+    /// The path of the folder containing this bookmark, with folder names
+    /// separated by '/'
+    #[serde(default)]
+    folder: Option<String>,
+    /// When the bookmark was added
+    #[serde(default)]
+    created: Option<DateTime<Local>>,
+    /// When the bookmarked page was last opened
+    #[serde(default)]
+    visited: Option<DateTime<Local>>,
+    /// Typing this in the address bar opens the bookmark, with any words
+    /// after it replacing `%s` in the url
+    #[serde(default)]
+    keyword: Option<String>,
+    /// The result of the last check for a broken link
+    #[serde(default)]
+    check: Option<LinkCheck>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -34,6 +170,8 @@ pub struct BookmarkBuilder {
     description: Option<String>,
     url: String,
     tags: Vec<String>,
+    folder: Option<String>,
+    keyword: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -42,6 +180,18 @@ pub struct Bookmarks {
     pub all: HashMap<String, Bookmark>,
     /// map tag name to vec of url strings
     pub tags: HashMap<String, Vec<String>>,
+    /// The most recently removed bookmark, kept so that the removal can be
+    /// undone
+    #[serde(skip)]
+    removed: Option<Bookmark>,
+    /// How many of how many bookmarks have been checked, while checking for
+    /// broken links
+    #[serde(skip)]
+    checking: Option<(usize, usize)>,
+    /// The bookmarks file as it was last read or written, which is compared
+    /// with the file on disk to find changes made by another instance
+    #[serde(skip)]
+    saved: Option<toml::Value>,
 }
 
 impl BookmarkBuilder {
@@ -77,6 +227,18 @@ impl BookmarkBuilder {
         self
     }
 
+    #[must_use]
+    pub fn folder(mut self, folder: Option<&str>) -> Self {
+        self.folder = folder.and_then(normalize_folder);
+        self
+    }
+
+    #[must_use]
+    pub fn keyword(mut self, keyword: Option<&str>) -> Self {
+        self.keyword = keyword.and_then(normalize_keyword);
+        self
+    }
+
     #[must_use]
     pub fn build(self) -> Bookmark {
         Bookmark {
@@ -84,6 +246,11 @@ impl BookmarkBuilder {
             description: self.description,
             url: self.url,
             tags: self.tags,
+            folder: self.folder,
+            created: None,
+            visited: None,
+            keyword: self.keyword,
+            check: None,
         }
     }
 }
@@ -105,6 +272,8 @@ impl From<&BookmarkEditor> for Bookmark {
                     .map(std::string::ToString::to_string)
                     .collect(),
             )
+            .folder(Some(editor.folder().as_str()))
+            .keyword(Some(editor.keyword().as_str()))
             .build()
     }
 }
@@ -158,56 +327,330 @@ impl Bookmark {
     pub fn has_tag(&self, tag: &str) -> bool {
         self.tags.contains(&String::from(tag))
     }
+
+    #[must_use]
+    pub fn folder(&self) -> Option<String> {
+        self.folder.clone()
+    }
+
+    pub fn set_folder(&mut self, folder: Option<&str>) {
+        self.folder = folder.and_then(normalize_folder);
+    }
+
+    #[must_use]
+    pub fn created(&self) -> Option<DateTime<Local>> {
+        self.created
+    }
+
+    #[must_use]
+    pub fn visited(&self) -> Option<DateTime<Local>> {
+        self.visited
+    }
+
+    #[must_use]
+    pub fn check(&self) -> Option<&LinkCheck> {
+        self.check.as_ref()
+    }
+
+    #[must_use]
+    pub fn keyword(&self) -> Option<String> {
+        self.keyword.clone()
+    }
+
+    pub fn set_keyword(&mut self, keyword: Option<&str>) {
+        self.keyword = keyword.and_then(normalize_keyword);
+    }
+
+    /// Whether the url contains `%s`, to be replaced by a query
+    #[must_use]
+    pub fn is_template(&self) -> bool {
+        self.url.contains("%s")
+    }
+
+    /// Fills in the url with `query`. A template has `%s` replaced with the
+    /// encoded query, while any other url has the query appended, as is done
+    /// for search engines. Without a query a template loses an empty `?%s`,
+    /// so that a Gemini server will ask for input instead.
+    #[must_use]
+    pub fn expand(&self, query: &str) -> String {
+        let encoded = urlencoding::encode(query);
+        if query.is_empty() {
+            String::from(self.url.replace("%s", "").trim_end_matches('?'))
+        } else if self.is_template() {
+            self.url.replace("%s", &encoded)
+        } else {
+            format!(
+                "{}?{encoded}",
+                self.url.split('?').next().unwrap_or_default()
+            )
+        }
+    }
+}
+
+impl Bookmark {
+    /// Returns this bookmark with the tags of `other` added, and the folder,
+    /// keyword and dates of `other` filled in where this bookmark has none
+    fn absorb(&self, other: &Self) -> Self {
+        let mut tags = self.tags.clone();
+        for tag in &other.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        Self {
+            tags,
+            folder: self.folder.clone().or_else(|| other.folder.clone()),
+            created: self.created.or(other.created),
+            visited: self.visited.or(other.visited),
+            keyword: self.keyword.clone().or_else(|| other.keyword.clone()),
+            ..self.clone()
+        }
+    }
+
+    fn to_gmi(&self) -> String {
+        let url = urlencoding::encode(&self.url);
+        let mut gmi = format!(
+            "### Name: {}\nDescription:\n> {}\nTags: {}\n",
+            &self.name,
+            match &self.description {
+                Some(d) => d,
+                None => "none",
+            },
+            &self.tags.join(", "),
+        );
+        if let Some(created) = self.created {
+            let _ = writeln!(gmi, "Added: {}", created.format("%Y-%m-%d"));
+        }
+        if let Some(visited) = self.visited {
+            let _ = writeln!(gmi, "Last visited: {}", visited.format("%Y-%m-%d %H:%M"));
+        }
+        if let Some(keyword) = &self.keyword {
+            let _ = writeln!(gmi, "Keyword: {keyword}");
+        }
+        if let Some(check) = self.check.as_ref().filter(|c| c.is_broken()) {
+            let _ = writeln!(gmi, "Broken link: {}", check.describe());
+        }
+        if let Some(folder) = &self.folder {
+            let _ = writeln!(gmi, "=> {} Folder: {folder}", folder_url(folder));
+        }
+        let _ = writeln!(
+            gmi,
+            "=> {}\n=> eva://bookmarks/edit?{url} Edit\n=> eva://bookmarks/delete?{url} Delete",
+            &self.url,
+        );
+        gmi
+    }
+
+    /// The entry for this bookmark in the link check report, with links to
+    /// fix it
+    fn check_to_gmi(&self, check: &LinkCheck) -> String {
+        let url = urlencoding::encode(&self.url);
+        let mut gmi = format!(
+            "### {}\n{}\n=> {}\n",
+            &self.name,
+            check.describe(),
+            &self.url
+        );
+        if let Some(target) = &check.target {
+            if check.can_follow() {
+                let _ = writeln!(
+                    gmi,
+                    "=> {target} Moved to {target}\n=> eva://bookmarks/check/update?{url} Update the bookmark",
+                );
+            } else {
+                let _ = writeln!(gmi, "=> {target} Temporarily moved to {target}");
+            }
+        }
+        let _ = writeln!(gmi, "=> eva://bookmarks/check/delete?{url} Delete");
+        gmi
+    }
 }
 
 impl Bookmarks {
+    fn header(view: &BookmarkView) -> String {
+        let mut header = String::from(
+            "# Bookmarks\n\n=> eva://bookmarks/search Search bookmarks\n\
+            => eva://bookmarks/tags Tags\n\
+            => eva://bookmarks/export Export bookmarks\n\
+            => eva://bookmarks/import Import bookmarks\n\
+            => eva://bookmarks/check Check for broken links\n\n",
+        );
+        for (sort, label) in [
+            (BookmarkSort::Name, "name"),
+            (BookmarkSort::Added, "date added"),
+            (BookmarkSort::Visited, "last visited"),
+        ] {
+            if sort == view.sort {
+                let _ = writeln!(header, "Sorted by {label}");
+            } else {
+                let _ = writeln!(
+                    header,
+                    "=> eva://bookmarks/sort?{} Sort by {label}",
+                    sort.name()
+                );
+            }
+        }
+        let _ = writeln!(
+            header,
+            "=> eva://bookmarks/group {}\n",
+            if view.group_by_tag {
+                "Stop grouping by tag"
+            } else {
+                "Group by tag"
+            },
+        );
+        header
+    }
+
     #[must_use]
-    pub fn to_gmi(&self) -> String {
-        let mut page = String::from("# Bookmarks\n\n=> eva://bookmarks/tags Tags\n\n");
-        for bookmark in self.all.values() {
+    pub fn to_gmi(&self, view: &BookmarkView) -> String {
+        let mut page = Self::header(view);
+        if let Some(removed) = &self.removed {
             let _ = writeln!(
                 page,
-                "### Name: {}\nDescription:\n> {}\nTags: {}\n=> {}\n",
-                &bookmark.name,
-                match &bookmark.description {
-                    Some(d) => d,
-                    None => "none",
-                },
-                &bookmark.tags.join(", "),
-                &bookmark.url,
+                "Deleted {}\n=> eva://bookmarks/undo Undo\n",
+                &removed.name
             );
         }
+        self.folder_contents_to_gmi(None, view, &mut page);
+        page
+    }
+
+    /// Returns the bookmarks matching every word of `query`, ignoring case,
+    /// sorted by name. Each word may match the name, description, url or any
+    /// tag of a bookmark.
+    #[must_use]
+    pub fn search(&self, query: &str) -> Vec<&Bookmark> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        let mut results: Vec<&Bookmark> = self
+            .all
+            .values()
+            .filter(|bm| {
+                let fields: Vec<String> = [&bm.name, &bm.url]
+                    .into_iter()
+                    .chain(&bm.description)
+                    .chain(&bm.keyword)
+                    .chain(&bm.tags)
+                    .map(|f| f.to_lowercase())
+                    .collect();
+                words
+                    .iter()
+                    .all(|w| fields.iter().any(|f| f.contains(w.as_str())))
+            })
+            .collect();
+        results.sort_by_cached_key(|bm| (bm.name.to_lowercase(), bm.url.clone()));
+        results
+    }
+
+    #[must_use]
+    pub fn search_to_gmi(&self, query: &str, view: &BookmarkView) -> String {
+        let results = sorted(self.search(query).into_iter(), view.sort);
+        let mut page = Self::header(view);
+        let _ = writeln!(
+            page,
+            "=> eva://bookmarks Show all bookmarks\n\n{} result{} for \"{query}\"\n",
+            results.len(),
+            if results.len() == 1 { "" } else { "s" },
+        );
+        for bookmark in results {
+            let _ = writeln!(page, "{}", bookmark.to_gmi());
+        }
+        page
+    }
+
+    /// Every folder which contains bookmarks, along with its parents, sorted
+    #[must_use]
+    pub fn folders(&self) -> Vec<String> {
+        folder_paths(self.all.values().filter_map(|b| b.folder.as_deref()))
+    }
+
+    /// The folders directly inside of `parent`, or at the top level if
+    /// `parent` is `None`
+    #[must_use]
+    pub fn subfolders(&self, parent: Option<&str>) -> Vec<String> {
+        child_folders(
+            self.all.values().filter_map(|b| b.folder.as_deref()),
+            parent,
+        )
+    }
+
+    /// Writes links to the subfolders of `folder` followed by the bookmarks
+    /// it contains, which may be grouped under a heading for each tag
+    fn folder_contents_to_gmi(&self, folder: Option<&str>, view: &BookmarkView, page: &mut String) {
+        let subfolders = self.subfolders(folder);
+        if !subfolders.is_empty() {
+            page.push_str("## Folders\n");
+            for sub in &subfolders {
+                let name = sub.rsplit('/').next().unwrap_or(sub);
+                let _ = writeln!(page, "=> {} {name}", folder_url(sub));
+            }
+            page.push('\n');
+        }
+        let bookmarks = sorted(
+            self.all.values().filter(|b| b.folder.as_deref() == folder),
+            view.sort,
+        );
+        if !view.group_by_tag {
+            for bookmark in bookmarks {
+                let _ = writeln!(page, "{}", bookmark.to_gmi());
+            }
+            return;
+        }
+        let tags: BTreeSet<&String> = bookmarks.iter().flat_map(|b| &b.tags).collect();
+        for tag in tags {
+            let _ = writeln!(page, "## {tag}\n");
+            for bookmark in bookmarks.iter().filter(|b| b.tags.contains(tag)) {
+                let _ = writeln!(page, "{}", bookmark.to_gmi());
+            }
+        }
+        let untagged: Vec<&&Bookmark> = bookmarks.iter().filter(|b| b.tags.is_empty()).collect();
+        if !untagged.is_empty() {
+            page.push_str("## Untagged\n\n");
+            for bookmark in untagged {
+                let _ = writeln!(page, "{}", bookmark.to_gmi());
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn folder_to_gmi(&self, folder: &str, view: &BookmarkView) -> String {
+        let mut page = format!("# Bookmarks in {folder}\n\n");
+        match folder.rsplit_once('/') {
+            Some((parent, _)) => {
+                let _ = writeln!(page, "=> {} Up to {parent}\n", folder_url(parent));
+            }
+            None => page.push_str("=> eva://bookmarks Up to all bookmarks\n\n"),
+        }
+        self.folder_contents_to_gmi(Some(folder), view, &mut page);
         page
     }
 
     #[must_use]
     pub fn tags_to_gmi(&self) -> String {
-        let mut page = String::from("# Bookmark Tags\n\n");
-        for tag in self.tags.keys() {
-            let _ = writeln!(page, "=> eva://bookmarks/tags/{} {}", &tag, &tag);
+        let mut page = String::from("# Bookmark Tags\n");
+        let mut tags: Vec<(&String, &Vec<String>)> = self.tags.iter().collect();
+        tags.sort();
+        for (tag, urls) in tags {
+            let url = tag_url(tag);
+            let _ = writeln!(
+                page,
+                "\n### {tag}\n{} bookmark{}\n=> {url} Show bookmarks\n\
+                => {url}/rename Rename\n=> {url}/merge Merge into another tag\n\
+                => {url}/delete Delete",
+                urls.len(),
+                if urls.len() == 1 { "" } else { "s" },
+            );
         }
         page.push_str("--\n=> eva://bookmarks back");
         page
     }
 
     #[must_use]
-    pub fn tag_to_gmi(&self, tag: &str) -> Option<String> {
+    pub fn tag_to_gmi(&self, tag: &str, view: &BookmarkView) -> Option<String> {
         if let Some(keys) = self.tags.get(tag) {
             let mut page = format!("# Bookmarks tagged {}\n\n", tag);
-            for key in keys {
-                if let Some(bookmark) = self.all.get(key) {
-                    let _ = write!(
-                        page,
-                        "### Name: {}\nDescription:\n> {}\nTags: {}\n=> {}\n\n",
-                        &bookmark.name,
-                        match &bookmark.description {
-                            Some(d) => d,
-                            None => "none",
-                        },
-                        &bookmark.tags.join(", "),
-                        &bookmark.url,
-                    );
-                }
+            for bookmark in sorted(keys.iter().filter_map(|k| self.all.get(k)), view.sort) {
+                let _ = writeln!(page, "{}", bookmark.to_gmi());
             }
             page.push_str("--\n=> eva://bookmarks/tags back");
             Some(page)
@@ -216,40 +659,356 @@ impl Bookmarks {
         }
     }
 
+    /// Adds or saves `bookmark`. A bookmark for a url which is already
+    /// bookmarked keeps the dates it was added and last visited, while a new
+    /// bookmark is dated now unless it already has a date. Bookmarking the
+    /// url of the most recently removed bookmark again means there is no
+    /// longer anything to undo.
     pub fn update(&mut self, bookmark: &Bookmark) {
-        self.all.insert(bookmark.url.clone(), bookmark.clone());
-        for tag in &bookmark.tags {
-            match self.tags.get(tag) {
-                Some(t) => {
-                    let mut v = t.clone();
-                    v.push(bookmark.url.clone());
-                    self.tags.insert(tag.to_string(), v);
-                }
-                None => {
-                    let _in = self
-                        .tags
-                        .insert(tag.to_string(), vec![bookmark.url.clone()]);
+        if self
+            .removed
+            .as_ref()
+            .map_or(false, |removed| removed.url == bookmark.url)
+        {
+            self.removed = None;
+        }
+        let mut bookmark = bookmark.clone();
+        match self.all.get(&bookmark.url) {
+            Some(old) => {
+                bookmark.created = bookmark.created.or(old.created);
+                bookmark.visited = bookmark.visited.or(old.visited);
+                bookmark.check = bookmark.check.or_else(|| old.check.clone());
+            }
+            None => {
+                bookmark.created = bookmark.created.or_else(|| Some(Local::now()));
+            }
+        }
+        if let Some(keyword) = &bookmark.keyword {
+            // A keyword can only open one bookmark
+            for other in self.all.values_mut() {
+                if other.url != bookmark.url && other.keyword.as_ref() == Some(keyword) {
+                    other.keyword = None;
                 }
             }
         }
-        for (tag, urls) in &self.tags.clone() {
-            if urls.is_empty() {
-                let _t = self.tags.remove(tag);
-            } else {
-                let mut u = urls.clone();
-                u.sort();
-                u.dedup();
-                if !bookmark.has_tag(tag) {
-                    u.retain(|x| x != &bookmark.url);
+        self.unindex(&bookmark.url);
+        for tag in &bookmark.tags {
+            let urls = self.tags.entry(tag.clone()).or_default();
+            if !urls.contains(&bookmark.url) {
+                urls.push(bookmark.url.clone());
+                urls.sort();
+            }
+        }
+        self.all.insert(bookmark.url.clone(), bookmark);
+    }
+
+    /// Saves `bookmark` in place of the bookmark for `old_url`, for when the
+    /// url of an existing bookmark has been edited
+    pub fn replace(&mut self, old_url: &str, bookmark: &Bookmark) {
+        if old_url != bookmark.url {
+            if let Some(old) = self.all.remove(old_url) {
+                self.unindex(old_url);
+                let bookmark = Bookmark {
+                    created: bookmark.created.or(old.created),
+                    visited: bookmark.visited.or(old.visited),
+                    ..bookmark.clone()
+                };
+                self.update(&bookmark);
+                return;
+            }
+        }
+        self.update(bookmark);
+    }
+
+    /// Records that the bookmarked page at `url` has just been opened.
+    /// Returns `false` if `url` is not bookmarked.
+    pub fn visit(&mut self, url: &str) -> bool {
+        match self.all.get_mut(url) {
+            Some(bookmark) => {
+                bookmark.visited = Some(Local::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Changes the url of the bookmark for `url` to where the last check
+    /// found that it has permanently moved. If the new url is already
+    /// bookmarked, that bookmark keeps its name and description and gains the
+    /// tags, folder and keyword of the moved one. Returns `false` if it was
+    /// not permanently redirected.
+    pub fn follow_redirect(&mut self, url: &str) -> bool {
+        let bookmark = match self.all.get(url) {
+            Some(b) => b,
+            None => return false,
+        };
+        let target = match &bookmark.check {
+            Some(check) if check.can_follow() => check.target.clone().unwrap_or_default(),
+            _ => return false,
+        };
+        let bookmark = match self.all.get(&target) {
+            Some(existing) if target != url => existing.absorb(bookmark),
+            _ => Bookmark {
+                url: target,
+                check: None,
+                ..bookmark.clone()
+            },
+        };
+        self.replace(url, &bookmark);
+        true
+    }
+
+    /// Follows every redirect found by the last check, returning the number
+    /// of bookmarks which were updated
+    pub fn follow_redirects(&mut self) -> usize {
+        let urls: Vec<String> = self
+            .all
+            .values()
+            .filter(|b| b.check.as_ref().map_or(false, LinkCheck::can_follow))
+            .map(|b| b.url.clone())
+            .collect();
+        urls.iter().filter(|url| self.follow_redirect(url)).count()
+    }
+
+    #[must_use]
+    pub fn is_checking(&self) -> bool {
+        self.checking.is_some()
+    }
+
+    /// Checks every bookmark for a broken link using `check`, recording the
+    /// result on each bookmark and calling `progress` after each one. The
+    /// lock is only held while recording results, so this is meant to be run
+    /// on its own thread.
+    /// # Panics
+    /// Panics if the mutex has been poisoned
+    pub fn check_links<C, P>(bookmarks: &Mutex<Self>, check: C, mut progress: P)
+    where
+        C: Fn(&str) -> LinkCheck + Sync,
+        P: FnMut(),
+    {
+        let mut bmarks = bookmarks.lock().unwrap();
+        let urls: Vec<String> = bmarks.all.keys().cloned().collect();
+        bmarks.checking = Some((0, urls.len()));
+        drop(bmarks);
+        check_all(urls, CHECK_THREADS, check, |url, result| {
+            let mut bmarks = bookmarks.lock().unwrap();
+            if let Some(bookmark) = bmarks.all.get_mut(&url) {
+                bookmark.check = Some(result);
+            }
+            if let Some((done, _)) = &mut bmarks.checking {
+                *done += 1;
+            }
+            drop(bmarks);
+            progress();
+        });
+        let mut bmarks = bookmarks.lock().unwrap();
+        bmarks.checking = None;
+        if let Err(e) = bmarks.save() {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Lists the bookmarks which the last check found to be redirected or
+    /// broken
+    #[must_use]
+    pub fn check_report_to_gmi(&self) -> String {
+        let mut page = String::from("# Broken Links\n\n=> eva://bookmarks back\n");
+        if let Some((done, total)) = self.checking {
+            let _ = writeln!(page, "\nChecking bookmarks, {done} of {total} done");
+        } else {
+            page.push_str("=> eva://bookmarks/check Check again\n");
+        }
+        let checked: Vec<(&Bookmark, &LinkCheck)> = sorted(self.all.values(), BookmarkSort::Name)
+            .into_iter()
+            .filter_map(|b| b.check.as_ref().map(|c| (b, c)))
+            .collect();
+        if let Some(last) = checked.iter().map(|(_, c)| c.checked).max() {
+            let _ = writeln!(page, "\nLast checked {}", last.format("%Y-%m-%d %H:%M"));
+        }
+        let redirected: Vec<_> = checked.iter().filter(|(_, c)| c.target.is_some()).collect();
+        if !redirected.is_empty() {
+            let _ = writeln!(page, "\n## Redirected ({})", redirected.len());
+            if redirected.iter().any(|(_, c)| c.can_follow()) {
+                page.push_str(
+                    "=> eva://bookmarks/check/update Update all permanently redirected bookmarks\n",
+                );
+            }
+            for (bookmark, check) in redirected {
+                let _ = write!(page, "\n{}", bookmark.check_to_gmi(check));
+            }
+        }
+        let broken: Vec<_> = checked.iter().filter(|(_, c)| c.is_broken()).collect();
+        if !broken.is_empty() {
+            let _ = writeln!(page, "\n## Broken ({})", broken.len());
+            for (bookmark, check) in broken {
+                let _ = write!(page, "\n{}", bookmark.check_to_gmi(check));
+            }
+        }
+        let ok = checked
+            .iter()
+            .filter(|(_, c)| c.status == LinkStatus::Ok)
+            .count();
+        let skipped = checked
+            .iter()
+            .filter(|(_, c)| c.status == LinkStatus::Unsupported)
+            .count();
+        let _ = writeln!(
+            page,
+            "\n{ok} working, {skipped} skipped as they are not gemini links or are keyword templates",
+        );
+        page
+    }
+
+    /// Removes `url` from the tag index, along with any tags which no longer
+    /// refer to a bookmark
+    fn unindex(&mut self, url: &str) {
+        for urls in self.tags.values_mut() {
+            urls.retain(|u| u != url);
+        }
+        self.tags.retain(|_, urls| !urls.is_empty());
+    }
+
+    /// Rebuilds the tag index from the tags of each bookmark, also fixing any
+    /// bookmark which is stored under a url other than its own. If there is
+    /// already a bookmark for its own url, that bookmark gains its tags,
+    /// folder and keyword. Returns `true` if anything needed to be repaired.
+    pub fn rebuild_index(&mut self) -> bool {
+        let mut repaired = false;
+        let misplaced: Vec<String> = self
+            .all
+            .iter()
+            .filter(|(url, bm)| **url != bm.url)
+            .map(|(url, _)| url.clone())
+            .collect();
+        for url in misplaced {
+            if let Some(bm) = self.all.remove(&url) {
+                let merged = match self.all.get(&bm.url) {
+                    Some(existing) => existing.absorb(&bm),
+                    None => bm,
+                };
+                let _old = self.all.insert(merged.url.clone(), merged);
+                repaired = true;
+            }
+        }
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for bm in self.all.values() {
+            for tag in &bm.tags {
+                tags.entry(tag.clone()).or_default().push(bm.url.clone());
+            }
+        }
+        for urls in tags.values_mut() {
+            urls.sort();
+            urls.dedup();
+        }
+        if tags != self.tags {
+            self.tags = tags;
+            repaired = true;
+        }
+        repaired
+    }
+
+    /// Renames `tag` to `new` on every bookmark which has it. Renaming to a
+    /// tag which already exists merges the two. Returns the number of
+    /// bookmarks which were changed.
+    pub fn rename_tag(&mut self, tag: &str, new: &str) -> usize {
+        self.merge_tags(&[tag], new)
+    }
+
+    /// Replaces each of `tags` with `into` on every bookmark which has any of
+    /// them. Whitespace in `into` is replaced with '_', as tags are separated
+    /// by whitespace in the editor. Returns the number of bookmarks which
+    /// were changed.
+    pub fn merge_tags(&mut self, tags: &[&str], into: &str) -> usize {
+        let into = into.split_whitespace().collect::<Vec<_>>().join("_");
+        if into.is_empty() {
+            return 0;
+        }
+        let mut changed = 0;
+        for bm in self.all.values_mut() {
+            let mut new_tags: Vec<String> = vec![];
+            for tag in &bm.tags {
+                let tag = if tags.contains(&tag.as_str()) {
+                    &into
+                } else {
+                    tag
+                };
+                if !new_tags.contains(tag) {
+                    new_tags.push(tag.clone());
                 }
-                self.tags.insert(tag.to_string(), u);
+            }
+            if new_tags != bm.tags {
+                bm.tags = new_tags;
+                changed += 1;
+            }
+        }
+        self.rebuild_index();
+        changed
+    }
+
+    /// Removes `tag` from every bookmark, returning the number of bookmarks
+    /// which had it
+    pub fn delete_tag(&mut self, tag: &str) -> usize {
+        let mut changed = 0;
+        for bm in self.all.values_mut() {
+            if bm.has_tag(tag) {
+                bm.remove_tag(tag);
+                changed += 1;
             }
         }
+        let _urls = self.tags.remove(tag);
+        changed
     }
 
+    /// Removes the bookmark for `url`, along with any tags which no longer
+    /// refer to a bookmark. The removal can be reversed with `undo_remove`.
+    pub fn remove(&mut self, url: &str) -> Option<Bookmark> {
+        let bookmark = self.all.remove(url)?;
+        self.unindex(url);
+        self.removed = Some(bookmark.clone());
+        Some(bookmark)
+    }
+
+    /// Restores the most recently removed bookmark. Returns `false` if there
+    /// was nothing to restore.
+    pub fn undo_remove(&mut self) -> bool {
+        match self.removed.take() {
+            Some(bookmark) => {
+                self.update(&bookmark);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Writes every bookmark in `format`
+    #[must_use]
+    pub fn export(&self, format: Format) -> String {
+        format::export(self.all.values(), format)
+    }
+
+    /// Adds the bookmarks read from `text`, returning how many were read.
+    /// Urls which are already bookmarked gain any new tags, and a folder,
+    /// keyword or dates if they had none, but otherwise keep their name and
+    /// description.
+    pub fn import(&mut self, text: &str, format: Format) -> usize {
+        let bookmarks = format::import(text, format);
+        let count = bookmarks.len();
+        for mut bookmark in bookmarks {
+            if let Some(old) = self.all.get(&bookmark.url) {
+                bookmark = old.absorb(&bookmark);
+            }
+            self.update(&bookmark);
+        }
+        count
+    }
+
+    /// Writes the bookmarks to disk. If the file was changed by another
+    /// instance since it was last read or written, those changes are merged
+    /// in first.
     /// # Errors
-    /// Returns error if unable to serialize toml or write to file
-    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+    /// Returns error if unable to read the bookmarks file on disk, or unable
+    /// to serialize toml or write to file
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
         let datadir = get_data_dir();
         let bmfile = get_bookmarks_file();
         if !datadir.exists() {
@@ -259,24 +1018,88 @@ impl Bookmarks {
             };
             std::fs::create_dir(&dd)?;
         }
-        let toml_string = toml::to_string(self)?;
-        std::fs::write(bmfile, toml_string)?;
+        let ours = toml::Value::try_from(&*self)?;
+        let value = match storage::read_value(&bmfile)? {
+            Some(theirs) if self.saved.as_ref() != Some(&theirs) => {
+                let merged = storage::merge(self.saved.as_ref(), &ours, &theirs);
+                self.take_merged(merged.clone())?;
+                merged
+            }
+            _ => ours,
+        };
+        let toml_string = toml::to_string(&value)?;
+        storage::write(&bmfile, toml_string)?;
+        self.saved = Some(value);
+        Ok(())
+    }
+
+    /// Picks up changes made to the bookmarks file by another instance of
+    /// Eva or by hand, merging them with any made here. Returns `true` if the
+    /// bookmarks changed.
+    /// # Errors
+    /// Returns error if unable to read or deserialize the bookmarks file, or
+    /// unable to save the merged bookmarks
+    pub fn reload(&mut self) -> Result<bool, Box<dyn Error>> {
+        let theirs = match storage::read_value(&get_bookmarks_file())? {
+            Some(t) if self.saved.as_ref() != Some(&t) => t,
+            _ => return Ok(false),
+        };
+        let ours = toml::Value::try_from(&*self)?;
+        let merged = storage::merge(self.saved.as_ref(), &ours, &theirs);
+        self.take_merged(merged.clone())?;
+        let unsaved = merged != theirs;
+        self.saved = Some(theirs);
+        if unsaved {
+            self.save()?;
+        }
+        Ok(merged != ours)
+    }
+
+    /// Replaces the bookmarks with the result of a merge, keeping the state
+    /// which is not saved to disk
+    fn take_merged(&mut self, merged: toml::Value) -> Result<(), Box<dyn Error>> {
+        let merged: Self = merged.try_into()?;
+        self.all = merged.all;
+        self.rebuild_index();
         Ok(())
     }
 
+    /// Loads the bookmarks, repairing and saving them again if the tag index
+    /// has fallen out of step with the bookmarks themselves. Failing to save
+    /// the repair is only logged, as the file itself was read.
     /// # Errors
     /// Returns error if unable to read bookmarks file or deserialize toml
     pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
-        let bmarks = get_bookmarks_file();
-        let bmarks = if bmarks.exists() {
-            std::fs::read_to_string(bmarks)?
-        } else {
-            return Ok(None);
+        let value = match storage::read_value(&get_bookmarks_file())? {
+            Some(v) => v,
+            None => return Ok(None),
         };
-        let bookmarks = toml::from_str(&bmarks)?;
+        let mut bookmarks: Self = value.clone().try_into()?;
+        bookmarks.saved = Some(value);
+        if bookmarks.rebuild_index() {
+            eprintln!("Repaired the bookmark tag index");
+            if let Err(e) = bookmarks.save() {
+                eprintln!("Unable to save the repaired bookmarks: {}", e);
+            }
+        }
         Ok(Some(bookmarks))
     }
 
+    /// Expands text typed into the address bar whose first word is the
+    /// keyword of a bookmark, such as `wp rust`, into the url to open
+    #[must_use]
+    pub fn expand_keyword(&self, text: &str) -> Option<String> {
+        let text = text.trim();
+        let (keyword, query) = match text.split_once(char::is_whitespace) {
+            Some((keyword, query)) => (keyword, query.trim()),
+            None => (text, ""),
+        };
+        self.all
+            .values()
+            .find(|b| b.keyword.as_deref() == Some(keyword))
+            .map(|b| b.expand(query))
+    }
+
     #[must_use]
     pub fn url_from_name(&self, name: &str) -> Option<String> {
         for bookmark in self.all.values() {
@@ -287,3 +1110,133 @@ impl Bookmarks {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(name: &str, url: &str, tags: &[&str]) -> Bookmark {
+        BookmarkBuilder::new()
+            .name(name)
+            .url(url)
+            .tags(tags.iter().map(|t| String::from(*t)).collect())
+            .build()
+    }
+
+    fn redirect(target: &str, permanent: bool) -> LinkCheck {
+        LinkCheck {
+            checked: Local::now(),
+            status: LinkStatus::Redirect,
+            message: None,
+            target: Some(String::from(target)),
+            permanent,
+        }
+    }
+
+    #[test]
+    fn follow_permanent_redirect() {
+        let mut bookmarks = Bookmarks::default();
+        let mut old = bookmark("Old", "gemini://old.example.org/", &["gemlog"]);
+        old.check = Some(redirect("gemini://new.example.org/", true));
+        bookmarks.update(&old);
+        assert!(bookmarks.follow_redirect("gemini://old.example.org/"));
+        assert!(!bookmarks.all.contains_key("gemini://old.example.org/"));
+        let new = &bookmarks.all["gemini://new.example.org/"];
+        assert_eq!(new.name, "Old");
+        assert!(new.check.is_none());
+        assert_eq!(bookmarks.tags["gemlog"], ["gemini://new.example.org/"]);
+    }
+
+    #[test]
+    fn temporary_redirect_is_not_followed() {
+        let mut bookmarks = Bookmarks::default();
+        let mut old = bookmark("Old", "gemini://old.example.org/", &[]);
+        old.check = Some(redirect("gemini://new.example.org/", false));
+        bookmarks.update(&old);
+        assert!(!bookmarks.follow_redirect("gemini://old.example.org/"));
+        assert_eq!(bookmarks.follow_redirects(), 0);
+        assert!(bookmarks.all.contains_key("gemini://old.example.org/"));
+        assert!(!bookmarks.check_report_to_gmi().contains("/check/update"));
+    }
+
+    #[test]
+    fn undo_survives_other_changes() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.update(&bookmark("A", "gemini://a.example.org/", &[]));
+        bookmarks.update(&bookmark("B", "gemini://b.example.org/", &[]));
+        assert!(bookmarks.remove("gemini://a.example.org/").is_some());
+        // Opening a bookmarked page and saving another bookmark both write
+        // to the bookmarks
+        assert!(bookmarks.visit("gemini://b.example.org/"));
+        bookmarks.update(&bookmark("C", "gemini://c.example.org/", &[]));
+        assert!(bookmarks.to_gmi(&BookmarkView::default()).contains("/undo"));
+        assert!(bookmarks.undo_remove());
+        assert!(bookmarks.all.contains_key("gemini://a.example.org/"));
+        assert!(!bookmarks.undo_remove());
+    }
+
+    #[test]
+    fn bookmarking_removed_url_again_clears_undo() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.update(&bookmark("A", "gemini://a.example.org/", &[]));
+        assert!(bookmarks.remove("gemini://a.example.org/").is_some());
+        bookmarks.update(&bookmark("New A", "gemini://a.example.org/", &[]));
+        assert!(!bookmarks.undo_remove());
+        assert_eq!(bookmarks.all["gemini://a.example.org/"].name, "New A");
+    }
+
+    #[test]
+    fn misplaced_bookmark_is_merged() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.update(&bookmark("Right", "gemini://a.example.org/", &["one"]));
+        let mut misplaced = bookmark("Wrong", "gemini://a.example.org/", &["two"]);
+        misplaced.folder = Some(String::from("Folder"));
+        misplaced.keyword = Some(String::from("a"));
+        let _old = bookmarks
+            .all
+            .insert(String::from("gemini://b.example.org/"), misplaced);
+        assert!(bookmarks.rebuild_index());
+        assert_eq!(bookmarks.all.len(), 1);
+        let repaired = &bookmarks.all["gemini://a.example.org/"];
+        assert_eq!(repaired.name, "Right");
+        assert_eq!(repaired.tags, ["one", "two"]);
+        assert_eq!(repaired.folder.as_deref(), Some("Folder"));
+        assert_eq!(repaired.keyword.as_deref(), Some("a"));
+        assert_eq!(bookmarks.tags["two"], ["gemini://a.example.org/"]);
+        assert!(!bookmarks.rebuild_index());
+    }
+
+    #[test]
+    fn misplaced_bookmark_is_moved() {
+        let mut bookmarks = Bookmarks::default();
+        let _old = bookmarks.all.insert(
+            String::from("gemini://b.example.org/"),
+            bookmark("A", "gemini://a.example.org/", &["one"]),
+        );
+        assert!(bookmarks.rebuild_index());
+        assert_eq!(bookmarks.all["gemini://a.example.org/"].name, "A");
+        assert_eq!(bookmarks.tags["one"], ["gemini://a.example.org/"]);
+    }
+
+    #[test]
+    fn redirect_to_existing_bookmark_merges() {
+        let mut bookmarks = Bookmarks::default();
+        let mut existing = bookmark("Existing", "gemini://new.example.org/", &["news"]);
+        existing.description = Some(String::from("Kept"));
+        bookmarks.update(&existing);
+        let mut old = bookmark("Old", "gemini://old.example.org/", &["gemlog", "news"]);
+        old.folder = Some(String::from("Blogs"));
+        old.keyword = Some(String::from("blog"));
+        old.check = Some(redirect("gemini://new.example.org/", true));
+        bookmarks.update(&old);
+        assert!(bookmarks.follow_redirect("gemini://old.example.org/"));
+        assert_eq!(bookmarks.all.len(), 1);
+        let merged = &bookmarks.all["gemini://new.example.org/"];
+        assert_eq!(merged.name, "Existing");
+        assert_eq!(merged.description.as_deref(), Some("Kept"));
+        assert_eq!(merged.tags, ["news", "gemlog"]);
+        assert_eq!(merged.folder.as_deref(), Some("Blogs"));
+        assert_eq!(merged.keyword.as_deref(), Some("blog"));
+        assert_eq!(bookmarks.tags["gemlog"], ["gemini://new.example.org/"]);
+    }
+}
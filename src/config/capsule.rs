@@ -0,0 +1,142 @@
+#![warn(clippy::all, clippy::pedantic)]
+//! Styles for individual capsules, such as a wider monospace font for one
+//! which is heavy on ascii art. They are kept in config.toml under
+//! `[capsules."host"]` and apply to that host and its subdomains.
+use {
+    super::{Colors, Font, Fonts},
+    rgba_simple::RGBA,
+    serde::{Deserialize, Serialize},
+};
+
+/// Colors and fonts used in place of the configured ones while a tab shows a
+/// page from one capsule. Anything left out keeps its usual value.
+#[derive(Clone, Default, Deserialize, Debug, Serialize)]
+#[serde(default)]
+pub struct Capsule {
+    /// The name of a theme whose colors and fonts are the starting point
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    #[serde(skip_serializing_if = "CapsuleColors::is_empty")]
+    pub colors: CapsuleColors,
+    #[serde(skip_serializing_if = "CapsuleFonts::is_empty")]
+    pub fonts: CapsuleFonts,
+}
+
+#[derive(Clone, Default, Deserialize, Debug, Serialize)]
+#[serde(default)]
+pub struct CapsuleColors {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_fg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_bg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_fg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote_bg: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<RGBA<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hover: Option<RGBA<u8>>,
+}
+
+impl CapsuleColors {
+    /// Whether no colors are overridden
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        [
+            self.fg,
+            self.bg,
+            self.pre_fg,
+            self.pre_bg,
+            self.quote_fg,
+            self.quote_bg,
+            self.link,
+            self.hover,
+        ]
+        .iter()
+        .all(Option::is_none)
+    }
+
+    /// Replaces the overridden colors in `colors`
+    pub fn apply(&self, colors: &mut Colors) {
+        let overrides = [
+            (self.fg, &mut colors.fg),
+            (self.bg, &mut colors.bg),
+            (self.pre_fg, &mut colors.pre_fg),
+            (self.pre_bg, &mut colors.pre_bg),
+            (self.quote_fg, &mut colors.quote_fg),
+            (self.quote_bg, &mut colors.quote_bg),
+            (self.link, &mut colors.link),
+            (self.hover, &mut colors.hover),
+        ];
+        for (color, target) in overrides {
+            if let Some(color) = color {
+                *target = color;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Debug, Serialize)]
+#[serde(default)]
+pub struct CapsuleFonts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pg: Option<Font>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre: Option<Font>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Font>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h1: Option<Font>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h2: Option<Font>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h3: Option<Font>,
+}
+
+impl CapsuleFonts {
+    /// Whether no fonts are overridden
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.pg,
+            &self.pre,
+            &self.quote,
+            &self.h1,
+            &self.h2,
+            &self.h3,
+        ]
+        .iter()
+        .all(|font| font.is_none())
+    }
+
+    /// Replaces the overridden fonts in `fonts`
+    pub fn apply(&self, fonts: &mut Fonts) {
+        let overrides = [
+            (&self.pg, &mut fonts.pg),
+            (&self.pre, &mut fonts.pre),
+            (&self.quote, &mut fonts.quote),
+            (&self.h1, &mut fonts.h1),
+            (&self.h2, &mut fonts.h2),
+            (&self.h3, &mut fonts.h3),
+        ];
+        for (font, target) in overrides {
+            if let Some(font) = font {
+                *target = font.clone();
+            }
+        }
+    }
+}
+
+/// Whether the style kept under `name` applies to `host`, which it does for
+/// the host itself and its subdomains
+#[must_use]
+pub fn matches(name: &str, host: &str) -> bool {
+    let name = name.trim_end_matches('.').to_lowercase();
+    let host = host.trim_end_matches('.').to_lowercase();
+    !name.is_empty() && (host == name || host.ends_with(&format!(".{name}")))
+}
@@ -1,16 +1,33 @@
 #![warn(clippy::all, clippy::pedantic)]
 use {
+    crate::storage,
+    lazy_static::lazy_static,
     rgba_simple::{PrimaryColor, RGBA},
     serde::{Deserialize, Serialize},
     std::{
+        collections::BTreeMap,
+        error::Error,
         fs, io,
         path::{Path, PathBuf},
+        sync::Mutex,
     },
 };
 
+mod capsule;
 mod fonts;
+mod theme;
 
-pub use fonts::{Font, Fonts};
+pub use {
+    capsule::{Capsule, CapsuleColors, CapsuleFonts},
+    fonts::{Font, Fonts},
+    theme::{get_theme_dir, Theme},
+};
+
+lazy_static! {
+    /// The contents of config.toml as it was last read or written, used to
+    /// tell which settings were changed elsewhere in the meantime
+    static ref SAVED: Mutex<Option<toml::Value>> = Mutex::new(None);
+}
 
 /// Returns an OS appropriate configuration directory path
 ///
@@ -176,6 +193,9 @@ impl Colors {
 pub enum NewPage {
     Home,
     Blank,
+    /// Restore the windows and tabs from the previous session on startup.
+    /// New tabs open the homepage.
+    LastSession,
 }
 
 impl Default for NewPage {
@@ -258,48 +278,238 @@ impl Default for General {
     }
 }
 
+#[derive(Clone, Deserialize, Debug, Serialize)]
+pub struct History {
+    /// Entries not visited in this many days are removed. Zero keeps entries
+    /// regardless of age.
+    pub max_age: u32,
+    /// The largest number of entries to keep. Zero means no limit.
+    pub max_entries: u32,
+    pub clear_on_exit: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            max_age: 90,
+            max_entries: 10_000,
+            clear_on_exit: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, Eq, PartialEq, Serialize)]
+pub enum BookmarkSort {
+    Name,
+    /// Newest first
+    Added,
+    /// Most recently visited first
+    Visited,
+}
+
+impl Default for BookmarkSort {
+    fn default() -> Self {
+        Self::Name
+    }
+}
+
+impl BookmarkSort {
+    /// The name used for this order in urls and the preferences dialog
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Added => "added",
+            Self::Visited => "visited",
+        }
+    }
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "name" => Some(Self::Name),
+            "added" => Some(Self::Added),
+            "visited" => Some(Self::Visited),
+            _ => None,
+        }
+    }
+}
+
+/// How the bookmark pages are laid out
+#[derive(Clone, Default, Deserialize, Debug, Serialize)]
+pub struct BookmarkView {
+    pub sort: BookmarkSort,
+    /// Show bookmarks under a heading for each of their tags
+    pub group_by_tag: bool,
+}
+
+/// Picks the colors of a light or a dark theme to match the desktop's
+/// preference, instead of the configured colors
+#[derive(Clone, Deserialize, Debug, Serialize)]
+pub struct Appearance {
+    pub follow_desktop: bool,
+    /// The name of the theme used when the desktop prefers light colors
+    pub light_theme: String,
+    /// The name of the theme used when the desktop prefers dark colors
+    pub dark_theme: String,
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self {
+            follow_desktop: false,
+            light_theme: String::from("Light"),
+            dark_theme: String::from("Eva"),
+        }
+    }
+}
+
 #[derive(Clone, Default, Deserialize, Debug, Serialize)]
 pub struct Config {
     pub general: General,
     pub colors: Colors,
     pub fonts: Fonts,
+    #[serde(default)]
+    pub history: History,
+    #[serde(default)]
+    pub bookmarks: BookmarkView,
+    #[serde(default)]
+    pub appearance: Appearance,
+    /// Styles for particular capsules, keyed by host
+    #[serde(default)]
+    pub capsules: BTreeMap<String, Capsule>,
 }
 
 impl Config {
-    /// Saves Config struct as a .toml file
+    /// The colors to show, which are those of the light or dark theme if
+    /// following the desktop, or else the configured colors
+    #[must_use]
+    pub fn colors_for(&self, dark: bool) -> Colors {
+        if self.appearance.follow_desktop {
+            let name = if dark {
+                &self.appearance.dark_theme
+            } else {
+                &self.appearance.light_theme
+            };
+            match Theme::find(name) {
+                Some(theme) => return theme.colors,
+                None => eprintln!("Theme not found: {}", name),
+            }
+        }
+        self.colors.clone()
+    }
+
+    /// The style for `host`. If several apply, the one for the longest, most
+    /// specific host is used.
+    #[must_use]
+    pub fn capsule(&self, host: &str) -> Option<&Capsule> {
+        self.capsules
+            .iter()
+            .filter(|(name, _)| capsule::matches(name, host))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, capsule)| capsule)
+    }
+
+    fn capsule_theme(capsule: &Capsule) -> Option<Theme> {
+        let name = capsule.theme.as_ref()?;
+        let theme = Theme::find(name);
+        if theme.is_none() {
+            eprintln!("Theme not found: {}", name);
+        }
+        theme
+    }
+
+    /// The colors to show on pages from `host`, or `None` if the capsule does
+    /// not have colors of its own
+    #[must_use]
+    pub fn capsule_colors(&self, host: &str, dark: bool) -> Option<Colors> {
+        let capsule = self.capsule(host)?;
+        let mut colors = match Self::capsule_theme(capsule) {
+            Some(theme) => theme.colors,
+            None if capsule.colors.is_empty() => return None,
+            None => self.colors_for(dark),
+        };
+        capsule.colors.apply(&mut colors);
+        Some(colors)
+    }
+
+    /// The fonts to use on pages from `host`, which are the configured fonts
+    /// unless the capsule has its own
+    #[must_use]
+    pub fn capsule_fonts(&self, host: Option<&str>) -> Fonts {
+        match host.and_then(|host| self.capsule(host)) {
+            Some(capsule) => {
+                let mut fonts = match Self::capsule_theme(capsule) {
+                    Some(theme) => theme.fonts,
+                    None => self.fonts.clone(),
+                };
+                capsule.fonts.apply(&mut fonts);
+                fonts
+            }
+            None => self.fonts.clone(),
+        }
+    }
+
+    /// Saves Config struct as a .toml file. Settings which were changed in
+    /// the file by another instance since it was last read or written are
+    /// merged in first, unless they were also changed here.
     /// # Errors
     /// Returns an io error if unable to save the config file
-    pub fn save_to_file(&self, file: &Path) -> Result<(), io::Error> {
-        let toml_string = match toml::to_string(&self) {
-            Ok(t) => t,
-            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+    /// # Panics
+    /// Panics if the mutex holding the saved config is poisoned
+    pub fn save_to_file(&mut self, file: &Path) -> Result<(), io::Error> {
+        let other = |e: &dyn Error| io::Error::new(io::ErrorKind::Other, e.to_string());
+        let ours = toml::Value::try_from(&*self).map_err(|e| other(&e))?;
+        let mut saved = SAVED.lock().unwrap();
+        let value = match storage::read_value(file) {
+            Ok(Some(theirs)) if saved.as_ref() != Some(&theirs) => {
+                let merged = storage::merge(saved.as_ref(), &ours, &theirs);
+                *self = merged.clone().try_into().map_err(|e| other(&e))?;
+                merged
+            }
+            _ => ours,
         };
-        fs::write(file, toml_string)?;
+        let toml_string = toml::to_string(&value).map_err(|e| other(&e))?;
+        storage::write(file, toml_string)?;
+        *saved = Some(value);
         Ok(())
     }
 
-    /// Deserializes config.toml into a `GfretConfig` struct
-    #[must_use]
-    pub fn from_file() -> Option<Self> {
-        let config_file = get_config_file();
-        let config_file = if config_file.exists() {
-            match fs::read_to_string(config_file) {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    return None;
-                }
-            }
-        } else {
-            return None;
+    /// Picks up changes made to config.toml by another instance of Eva or by
+    /// hand, merging them with any made here which are not yet saved.
+    /// Returns `true` if the config changed.
+    /// # Errors
+    /// Returns error if unable to read or deserialize config.toml
+    /// # Panics
+    /// Panics if the mutex holding the saved config is poisoned
+    pub fn reload(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut saved = SAVED.lock().unwrap();
+        let theirs = match storage::read_value(&get_config_file())? {
+            Some(t) if saved.as_ref() != Some(&t) => t,
+            _ => return Ok(false),
         };
-        let config: Self = match toml::from_str(&config_file) {
-            Ok(c) => c,
-            Err(e) => {
-                eprintln!("{}", e);
-                return None;
-            }
+        let ours = toml::Value::try_from(&*self)?;
+        let merged = storage::merge(saved.as_ref(), &ours, &theirs);
+        let changed = merged != ours;
+        if changed {
+            *self = merged.try_into()?;
+        }
+        *saved = Some(theirs);
+        Ok(changed)
+    }
+
+    /// Deserializes config.toml into a `GfretConfig` struct
+    /// # Errors
+    /// Returns an error if unable to read config.toml or deserialize toml
+    /// # Panics
+    /// Panics if the mutex holding the saved config is poisoned
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        let value = match storage::read_value(&get_config_file())? {
+            Some(v) => v,
+            None => return Ok(None),
         };
-        Some(config)
+        let config: Self = value.clone().try_into()?;
+        *SAVED.lock().unwrap() = Some(value);
+        Ok(Some(config))
     }
 }
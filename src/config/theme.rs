@@ -0,0 +1,242 @@
+#![warn(clippy::all, clippy::pedantic)]
+//! Named sets of colors and fonts. Several themes are built in, and the
+//! user's own are kept as separate toml files in the `themes` directory
+//! under the config directory, where they can be shared by copying the file.
+use {
+    super::{get_config_dir, Colors, Fonts},
+    crate::storage,
+    lazy_static::lazy_static,
+    rgba_simple::RGBA,
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        fs,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    },
+};
+
+lazy_static! {
+    /// The built in and user themes, read from disk when first needed and
+    /// again after the themes directory changes
+    static ref THEMES: Mutex<Option<Vec<Theme>>> = Mutex::new(None);
+}
+
+/// Returns the directory holding the user's themes
+#[must_use]
+pub fn get_theme_dir() -> PathBuf {
+    let mut dir = get_config_dir();
+    dir.push("themes");
+    dir
+}
+
+fn rgb(red: u8, green: u8, blue: u8) -> RGBA<u8> {
+    RGBA {
+        red,
+        green,
+        blue,
+        alpha: 255,
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, Serialize)]
+pub struct Theme {
+    pub name: String,
+    pub colors: Colors,
+    #[serde(default)]
+    pub fonts: Fonts,
+}
+
+impl Theme {
+    /// The themes which come with Eva
+    #[must_use]
+    pub fn builtin() -> Vec<Self> {
+        vec![
+            Self {
+                name: String::from("Eva"),
+                colors: Colors::default(),
+                fonts: Fonts::default(),
+            },
+            Self {
+                name: String::from("Light"),
+                colors: Colors {
+                    fg: rgb(36, 31, 49),
+                    bg: rgb(250, 250, 250),
+                    pre_fg: rgb(36, 31, 49),
+                    pre_bg: rgb(235, 235, 235),
+                    quote_fg: rgb(38, 95, 55),
+                    quote_bg: rgb(230, 242, 232),
+                    link: rgb(26, 95, 180),
+                    hover: rgb(192, 28, 40),
+                },
+                fonts: Fonts::default(),
+            },
+            Self {
+                name: String::from("Dark"),
+                colors: Colors {
+                    fg: rgb(222, 221, 218),
+                    bg: rgb(36, 36, 36),
+                    pre_fg: rgb(222, 221, 218),
+                    pre_bg: rgb(48, 48, 48),
+                    quote_fg: rgb(143, 240, 164),
+                    quote_bg: rgb(38, 56, 42),
+                    link: rgb(120, 174, 237),
+                    hover: rgb(246, 97, 81),
+                },
+                fonts: Fonts::default(),
+            },
+            Self {
+                name: String::from("High Contrast"),
+                colors: Colors {
+                    fg: rgb(255, 255, 255),
+                    bg: rgb(0, 0, 0),
+                    pre_fg: rgb(255, 255, 255),
+                    pre_bg: rgb(30, 30, 30),
+                    quote_fg: rgb(0, 255, 255),
+                    quote_bg: rgb(0, 0, 0),
+                    link: rgb(255, 255, 0),
+                    hover: rgb(255, 128, 0),
+                },
+                fonts: Fonts::default(),
+            },
+        ]
+    }
+
+    /// The built in themes followed by the user's own, sorted by name. A
+    /// user theme with the same name as a built in theme replaces it.
+    /// # Panics
+    /// Panics if the mutex holding the themes is poisoned
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        THEMES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Self::load)
+            .clone()
+    }
+
+    /// Forgets the themes which were read, so that the themes directory is
+    /// read again the next time they are needed
+    /// # Panics
+    /// Panics if the mutex holding the themes is poisoned
+    pub fn reload() {
+        *THEMES.lock().unwrap() = None;
+    }
+
+    /// Reads the user's themes from disk
+    fn load() -> Vec<Self> {
+        let mut themes = Self::builtin();
+        let mut user: Vec<Self> = match fs::read_dir(get_theme_dir()) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "toml"))
+                .filter_map(|path| match Self::from_file(&path) {
+                    Ok(theme) => Some(theme),
+                    Err(e) => {
+                        eprintln!("Unable to read theme {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        user.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        for theme in user {
+            match themes.iter_mut().find(|t| t.name == theme.name) {
+                Some(builtin) => *builtin = theme,
+                None => themes.push(theme),
+            }
+        }
+        themes
+    }
+
+    /// Looks up the theme called `name`
+    /// # Panics
+    /// Panics if the mutex holding the themes is poisoned
+    #[must_use]
+    pub fn find(name: &str) -> Option<Self> {
+        THEMES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Self::load)
+            .iter()
+            .find(|t| t.name == name)
+            .cloned()
+    }
+
+    /// Whether `colors` and `fonts` are exactly those of this theme
+    #[must_use]
+    pub fn matches(&self, colors: &Colors, fonts: &Fonts) -> bool {
+        let theme = (
+            toml::Value::try_from(&self.colors),
+            toml::Value::try_from(&self.fonts),
+        );
+        let other = (toml::Value::try_from(colors), toml::Value::try_from(fonts));
+        match (theme, other) {
+            ((Ok(a), Ok(b)), (Ok(c), Ok(d))) => a == c && b == d,
+            _ => false,
+        }
+    }
+
+    /// Reads a theme file
+    /// # Errors
+    /// Returns an error if unable to read the file or deserialize toml
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let theme: Self = toml::from_str(&text)?;
+        if theme.name.trim().is_empty() {
+            return Err(String::from("The theme has no name").into());
+        }
+        Ok(theme)
+    }
+
+    /// Writes the theme to the file at `path`, for sharing
+    /// # Errors
+    /// Returns an error if unable to serialize toml or write the file
+    pub fn export(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let toml_string = toml::to_string(self)?;
+        fs::write(path, toml_string)?;
+        Ok(())
+    }
+
+    /// The path of this theme in the user's theme directory, named after it
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        let stem: String = self
+            .name
+            .trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut path = get_theme_dir();
+        path.push(format!("{stem}.toml"));
+        path
+    }
+
+    /// Adds the theme to the user's themes, replacing any with the same name
+    /// # Errors
+    /// Returns an error if unable to create the theme directory, serialize
+    /// toml or write the file
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let dir = get_theme_dir();
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+        }
+        let toml_string = toml::to_string(self)?;
+        storage::write(&self.path(), toml_string)?;
+        Self::reload();
+        Ok(())
+    }
+
+    /// Reads the theme file at `path` and adds it to the user's themes,
+    /// returning the imported theme
+    /// # Errors
+    /// Returns an error if unable to read the file or save the theme
+    pub fn import(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let theme = Self::from_file(path)?;
+        theme.save()?;
+        Ok(theme)
+    }
+}
@@ -0,0 +1,393 @@
+//! A minimal Gemini client, used to fetch feeds and check bookmarks in the
+//! background without needing a `GemView`
+use {
+    super::known_hosts::Trust,
+    native_tls::{TlsConnector, TlsStream},
+    std::{
+        error::Error,
+        io::{self, BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpStream, ToSocketAddrs},
+        time::Duration,
+    },
+    url::Url,
+};
+
+/// How long to wait for a server before giving up on it
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The most redirects which will be followed for a single feed
+const MAX_REDIRECTS: usize = 5;
+
+/// Feeds larger than this are truncated
+const MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The longest header a server may send: a two digit status, a space, up to
+/// 1024 bytes of meta and the closing CRLF
+const MAX_HEADER: u64 = 1029;
+
+pub type FetchError = Box<dyn Error + Send + Sync>;
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    /// The url which was finally fetched, after following any redirects
+    pub url: Url,
+    pub mime: String,
+    pub body: String,
+}
+
+/// The replies to a request which are of interest for fetching feeds
+#[derive(Clone, Debug)]
+pub enum Reply {
+    Success { mime: String, body: String },
+    Redirect(String),
+}
+
+/// Fetches `url`, following redirects. Servers are checked against the
+/// known hosts in `trust`.
+/// # Errors
+/// Returns an error if the url is not a gemini url, the server can not be
+/// reached, or the server replies with anything other than success or a
+/// redirect
+pub fn fetch(url: &Url, trust: &Trust) -> Result<Response, FetchError> {
+    fetch_with(url, |url| {
+        let addr = resolve(url)?;
+        let stream = connect(&addr)?;
+        tls(url, stream, trust)
+    })
+}
+
+/// Fetches `url` over the streams returned by `open` for it and for each
+/// redirect target, following at most `MAX_REDIRECTS` redirects
+fn fetch_with<S, F>(url: &Url, mut open: F) -> Result<Response, FetchError>
+where
+    S: Read + Write,
+    F: FnMut(&Url) -> Result<S, FetchError>,
+{
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        if url.scheme() != "gemini" {
+            return Err(format!("Unsupported scheme: {}", url.scheme()).into());
+        }
+        let stream = open(&url)?;
+        match request(stream, &url)? {
+            Reply::Success { mime, body } => return Ok(Response { url, mime, body }),
+            Reply::Redirect(target) => url = url.join(&target)?,
+        }
+    }
+    Err("Too many redirects".into())
+}
+
+/// Looks up the address of the server for `url`
+/// # Errors
+/// Returns an error if the url has no host or the host can not be resolved
+pub fn resolve(url: &Url) -> Result<SocketAddr, FetchError> {
+    let host = url.host_str().ok_or("Missing host")?;
+    let addr = (host, url.port().unwrap_or(1965))
+        .to_socket_addrs()?
+        .next()
+        .ok_or("Unable to resolve host")?;
+    Ok(addr)
+}
+
+/// Opens a connection to `addr`, which gives up after `TIMEOUT`
+/// # Errors
+/// Returns an error if the server can not be reached
+pub fn connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Starts a TLS session over `stream` with the server for `url`, checking
+/// its certificate against the known hosts in `trust`
+/// # Errors
+/// Returns an error if the TLS handshake fails, or if the server presents a
+/// different certificate from the one it presented when first contacted
+pub fn tls(
+    url: &Url,
+    stream: TcpStream,
+    trust: &Trust,
+) -> Result<TlsStream<TcpStream>, FetchError> {
+    // Most capsules use self signed certificates, which are trusted on first
+    // use by checking them against the known hosts instead
+    let connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()?;
+    let host = url.host_str().unwrap_or_default();
+    let stream = connector.connect(host, stream)?;
+    let certificate = stream
+        .peer_certificate()?
+        .ok_or("The server did not present a certificate")?;
+    trust.verify(host, url.port().unwrap_or(1965), &certificate.to_der()?)?;
+    Ok(stream)
+}
+
+/// Sends the request for `url` over `stream` and reads only the header of
+/// the reply, returning its status and meta
+/// # Errors
+/// Returns an error if unable to write the request or read the header, or if
+/// the header does not start with a status
+pub fn status<S: Read + Write>(mut stream: S, url: &Url) -> Result<(u8, String), FetchError> {
+    write!(stream, "{url}\r\n")?;
+    stream.flush()?;
+    read_header(&mut BufReader::new(stream))
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(u8, String), FetchError> {
+    let mut header = String::new();
+    reader.take(MAX_HEADER).read_line(&mut header)?;
+    if !header.ends_with('\n') && header.len() as u64 == MAX_HEADER {
+        return Err("Header too long".into());
+    }
+    let header = header.trim_end();
+    if header.is_empty() {
+        return Err("Empty reply from server".into());
+    }
+    let (status, meta) = header.split_once(' ').unwrap_or((header, ""));
+    match status.parse() {
+        Ok(status) if (10..70).contains(&status) => Ok((status, String::from(meta.trim()))),
+        _ => Err(format!("Invalid reply: {header}").into()),
+    }
+}
+
+/// Sends the request for `url` over `stream` and reads the reply. This works
+/// over any stream, so it does not depend on a TLS connection.
+/// # Errors
+/// Returns an error if unable to write the request or read the reply, or if
+/// the reply is neither success nor a redirect
+pub fn request<S: Read + Write>(mut stream: S, url: &Url) -> Result<Reply, FetchError> {
+    write!(stream, "{url}\r\n")?;
+    stream.flush()?;
+    let mut reader = BufReader::new(stream.take(MAX_SIZE));
+    let (status, meta) = read_header(&mut reader)?;
+    match status / 10 {
+        2 => {
+            let mut body = vec![];
+            // Many servers close the connection without a TLS close_notify,
+            // which shows up as an error after the whole body has been read
+            if let Err(e) = reader.read_to_end(&mut body) {
+                if body.is_empty() {
+                    return Err(e.into());
+                }
+            }
+            Ok(Reply::Success {
+                mime: meta,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            })
+        }
+        3 => Ok(Reply::Redirect(meta)),
+        _ => Err(format!("Server replied: {status} {meta}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::feeds::mock::{self, MockStream},
+    };
+
+    fn url() -> Url {
+        Url::parse("gemini://example.org/gemlog/").unwrap()
+    }
+
+    #[test]
+    fn success() {
+        let mut stream = MockStream::new("20 text/gemini; lang=en\r\n# Gemlog\n");
+        match request(&mut stream, &url()).unwrap() {
+            Reply::Success { mime, body } => {
+                assert_eq!(mime, "text/gemini; lang=en");
+                assert_eq!(body, "# Gemlog\n");
+            }
+            Reply::Redirect(_) => panic!("expected success"),
+        }
+        assert_eq!(stream.output, b"gemini://example.org/gemlog/\r\n");
+    }
+
+    #[test]
+    fn redirect() {
+        let mut stream = MockStream::new("31 /posts/\r\n");
+        match request(&mut stream, &url()).unwrap() {
+            Reply::Redirect(target) => assert_eq!(target, "/posts/"),
+            Reply::Success { .. } => panic!("expected a redirect"),
+        }
+    }
+
+    #[test]
+    fn error_status() {
+        let mut stream = MockStream::new("51 Not found\r\n");
+        let e = request(&mut stream, &url()).unwrap_err();
+        assert_eq!(e.to_string(), "Server replied: 51 Not found");
+    }
+
+    #[test]
+    fn invalid_reply() {
+        let mut stream = MockStream::new("HTTP/1.1 200 OK\r\n");
+        assert!(request(&mut stream, &url()).is_err());
+        let mut stream = MockStream::new("");
+        let e = request(&mut stream, &url()).unwrap_err();
+        assert_eq!(e.to_string(), "Empty reply from server");
+    }
+
+    #[test]
+    fn oversized_header() {
+        let reply = format!("20 {}\r\n", "x".repeat(2048));
+        let e = request(&mut MockStream::new(reply.clone()), &url()).unwrap_err();
+        assert_eq!(e.to_string(), "Header too long");
+        let e = status(&mut MockStream::new(reply), &url()).unwrap_err();
+        assert_eq!(e.to_string(), "Header too long");
+        // The longest allowed meta still fits
+        let reply = format!("20 {}\r\n", "x".repeat(1024));
+        assert!(status(&mut MockStream::new(reply), &url()).is_ok());
+    }
+
+    #[test]
+    fn oversized_body() {
+        let header = "20 text/gemini\r\n";
+        let reply = format!("{header}{}", "x".repeat(usize::try_from(MAX_SIZE).unwrap()));
+        match request(&mut MockStream::new(reply), &url()).unwrap() {
+            Reply::Success { body, .. } => {
+                assert_eq!(body.len() as u64, MAX_SIZE - header.len() as u64);
+            }
+            Reply::Redirect(_) => panic!("expected success"),
+        }
+    }
+
+    /// Fetches `url` from streams replying with `replies`, which are keyed
+    /// by url, returning the urls which were requested along with the result
+    fn fetch_from(
+        url: &str,
+        replies: &[(&str, &str)],
+    ) -> (Vec<String>, Result<Response, FetchError>) {
+        let mut requested = vec![];
+        let result = fetch_with(&Url::parse(url).unwrap(), |url| {
+            requested.push(url.to_string());
+            replies
+                .iter()
+                .find(|(u, _)| *u == url.as_str())
+                .map(|(_, reply)| MockStream::new(*reply))
+                .ok_or_else(|| format!("Unable to connect to {url}").into())
+        });
+        (requested, result)
+    }
+
+    #[test]
+    fn fetch_follows_redirects() {
+        let (requested, result) = fetch_from(
+            "gemini://example.org/gemlog/",
+            &[
+                ("gemini://example.org/gemlog/", "31 /posts/\r\n"),
+                (
+                    "gemini://example.org/posts/",
+                    "30 gemini://example.net/\r\n",
+                ),
+                ("gemini://example.net/", "20 text/gemini\r\n# Posts\n"),
+            ],
+        );
+        assert_eq!(
+            requested,
+            [
+                "gemini://example.org/gemlog/",
+                "gemini://example.org/posts/",
+                "gemini://example.net/",
+            ]
+        );
+        let response = result.unwrap();
+        assert_eq!(response.url.as_str(), "gemini://example.net/");
+        assert_eq!(response.mime, "text/gemini");
+        assert_eq!(response.body, "# Posts\n");
+    }
+
+    #[test]
+    fn fetch_resolves_relative_redirects() {
+        let (requested, result) = fetch_from(
+            "gemini://example.org/gemlog/index.gmi",
+            &[
+                ("gemini://example.org/gemlog/index.gmi", "31 posts.gmi\r\n"),
+                (
+                    "gemini://example.org/gemlog/posts.gmi",
+                    "31 ../feed.gmi\r\n",
+                ),
+                ("gemini://example.org/feed.gmi", "20 text/gemini\r\n"),
+            ],
+        );
+        assert_eq!(requested.len(), 3);
+        assert_eq!(
+            result.unwrap().url.as_str(),
+            "gemini://example.org/feed.gmi"
+        );
+    }
+
+    #[test]
+    fn fetch_redirect_limit() {
+        // Each page redirects to the next one
+        let pages: Vec<(String, String)> = (0..=MAX_REDIRECTS + 1)
+            .map(|n| {
+                (
+                    format!("gemini://example.org/{n}"),
+                    format!("31 /{}\r\n", n + 1),
+                )
+            })
+            .collect();
+        let replies: Vec<(&str, &str)> = pages
+            .iter()
+            .map(|(url, reply)| (url.as_str(), reply.as_str()))
+            .collect();
+        let (requested, result) = fetch_from("gemini://example.org/0", &replies);
+        assert_eq!(requested.len(), MAX_REDIRECTS + 1);
+        assert_eq!(result.unwrap_err().to_string(), "Too many redirects");
+        // The last redirect which is allowed is still followed
+        let last = format!("gemini://example.org/{MAX_REDIRECTS}");
+        let replies: Vec<(&str, &str)> = replies
+            .into_iter()
+            .map(|(url, reply)| {
+                if url == last {
+                    (url, "20 text/gemini\r\n")
+                } else {
+                    (url, reply)
+                }
+            })
+            .collect();
+        let (_, result) = fetch_from("gemini://example.org/0", &replies);
+        assert_eq!(result.unwrap().url.as_str(), last);
+    }
+
+    #[test]
+    fn fetch_stops_at_unsupported_redirect() {
+        let (requested, result) = fetch_from(
+            "gemini://example.org/",
+            &[("gemini://example.org/", "31 https://example.org/\r\n")],
+        );
+        assert_eq!(requested.len(), 1);
+        assert_eq!(result.unwrap_err().to_string(), "Unsupported scheme: https");
+    }
+
+    #[test]
+    fn fetch_header_cap() {
+        let long = format!("20 {}\r\n", "x".repeat(2048));
+        let (_, result) = fetch_from(
+            "gemini://example.org/",
+            &[
+                ("gemini://example.org/", "31 /long\r\n"),
+                ("gemini://example.org/long", &long),
+            ],
+        );
+        assert_eq!(result.unwrap_err().to_string(), "Header too long");
+    }
+
+    #[test]
+    fn fake_server() {
+        let (addr, server) = mock::serve("20 text/gemini\r\n=> post.gmi 2022-11-05 A post\n");
+        let stream = connect(&addr).unwrap();
+        match request(stream, &url()).unwrap() {
+            Reply::Success { mime, body } => {
+                assert_eq!(mime, "text/gemini");
+                assert_eq!(body, "=> post.gmi 2022-11-05 A post\n");
+            }
+            Reply::Redirect(_) => panic!("expected success"),
+        }
+        assert_eq!(server.join().unwrap(), "gemini://example.org/gemlog/\r\n");
+    }
+}
@@ -0,0 +1,226 @@
+#![warn(clippy::all, clippy::pedantic)]
+//! The certificates which capsules presented the first time they were
+//! contacted in the background, so that a different certificate presented
+//! later is noticed. Most capsules use self signed certificates, so they are
+//! trusted on first use rather than checked against a certificate authority.
+use {
+    super::fetch::FetchError,
+    crate::{history::get_data_dir, storage},
+    chrono::prelude::*,
+    lazy_static::lazy_static,
+    ring::digest,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::Write,
+        fs,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+    },
+};
+
+lazy_static! {
+    /// Read from disk when the first capsule is contacted
+    static ref KNOWN_HOSTS: Mutex<Option<KnownHosts>> = Mutex::new(None);
+}
+
+#[must_use]
+pub fn get_known_hosts_file() -> PathBuf {
+    let mut file = get_data_dir();
+    file.push("known_hosts.toml");
+    file
+}
+
+/// Returns the sha256 fingerprint of a certificate in der format, as hex
+#[must_use]
+pub fn fingerprint(der: &[u8]) -> String {
+    let hash = digest::digest(&digest::SHA256, der);
+    let mut hex = String::with_capacity(64);
+    for byte in hash.as_ref() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Host {
+    pub fingerprint: String,
+    pub first_seen: DateTime<Local>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KnownHosts {
+    /// key is the host and port, as `host:port`
+    pub hosts: HashMap<String, Host>,
+}
+
+impl KnownHosts {
+    #[must_use]
+    pub fn contains(&self, host: &str, port: u16) -> bool {
+        self.hosts.contains_key(&format!("{host}:{port}"))
+    }
+
+    /// Checks the certificate `der` presented by `host` against the one it
+    /// presented before, remembering it if the host is new. Returns `true`
+    /// if the host was added.
+    /// # Errors
+    /// Returns an error if the host presented a different certificate before
+    pub fn verify(&mut self, host: &str, port: u16, der: &[u8]) -> Result<bool, FetchError> {
+        let key = format!("{host}:{port}");
+        let fingerprint = fingerprint(der);
+        match self.hosts.get(&key) {
+            Some(known) if known.fingerprint == fingerprint => Ok(false),
+            Some(_) => Err(format!(
+                "The certificate of {key} has changed since it was first seen. Remove it \
+                from {} to trust the new one.",
+                get_known_hosts_file().display()
+            )
+            .into()),
+            None => {
+                let _old = self.hosts.insert(
+                    key,
+                    Host {
+                        fingerprint,
+                        first_seen: Local::now(),
+                    },
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    /// Saves the known hosts as a .toml file
+    /// # Errors
+    /// Returns an error if unable to create the data directory, serialize
+    /// toml or write the file
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let datadir = get_data_dir();
+        if !datadir.exists() {
+            fs::create_dir_all(&datadir)?;
+        }
+        let toml_string = toml::to_string(self)?;
+        storage::write(&get_known_hosts_file(), toml_string)?;
+        Ok(())
+    }
+
+    /// Deserializes `known_hosts.toml`
+    /// # Errors
+    /// Returns an error if unable to read the file or deserialize toml
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        match storage::read_value(&get_known_hosts_file())? {
+            Some(value) => Ok(Some(value.try_into()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Where the certificates of capsules contacted for the first time are
+/// remembered
+#[derive(Clone, Debug)]
+pub enum Trust {
+    /// In `known_hosts.toml`
+    Saved,
+    /// Only in memory, for the connections made from a private window. Hosts
+    /// which are already in `known_hosts.toml` are still checked against it.
+    Private(Arc<Mutex<KnownHosts>>),
+}
+
+impl Trust {
+    /// Starts an empty set of known hosts for a private window
+    #[must_use]
+    pub fn private() -> Self {
+        Self::Private(Arc::new(Mutex::new(KnownHosts::default())))
+    }
+
+    /// Checks the certificate `der` presented by `host` against the known
+    /// hosts, remembering it if the host has not been contacted before
+    /// # Errors
+    /// Returns an error if the host presented a different certificate before,
+    /// or if the known hosts could not be read
+    /// # Panics
+    /// Panics if a mutex holding known hosts is poisoned
+    pub fn verify(&self, host: &str, port: u16, der: &[u8]) -> Result<(), FetchError> {
+        let mut saved = KNOWN_HOSTS.lock().unwrap();
+        if saved.is_none() {
+            *saved = Some(
+                KnownHosts::from_file()
+                    .map_err(|e| format!("Unable to read the known hosts: {e}"))?
+                    .unwrap_or_default(),
+            );
+        }
+        let saved = saved.get_or_insert_with(KnownHosts::default);
+        if self.remember(saved, host, port, der)? {
+            if let Err(e) = saved.save() {
+                eprintln!("{e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the certificate `der` presented by `host` against `saved`, or
+    /// against the hosts kept in memory for a private window. Returns `true`
+    /// if the host was added to `saved`.
+    fn remember(
+        &self,
+        saved: &mut KnownHosts,
+        host: &str,
+        port: u16,
+        der: &[u8],
+    ) -> Result<bool, FetchError> {
+        match self {
+            Self::Private(known_hosts) if !saved.contains(host, port) => {
+                let _added = known_hosts.lock().unwrap().verify(host, port, der)?;
+                Ok(false)
+            }
+            _ => saved.verify(host, port, der),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_on_first_use() {
+        let mut known_hosts = KnownHosts::default();
+        assert!(known_hosts.verify("example.org", 1965, b"first").unwrap());
+        assert!(!known_hosts.verify("example.org", 1965, b"first").unwrap());
+        assert!(known_hosts.verify("example.org", 1965, b"second").is_err());
+        // Each port is a separate host
+        assert!(known_hosts.verify("example.org", 1966, b"second").unwrap());
+    }
+
+    #[test]
+    fn private_hosts_stay_in_memory() {
+        let mut saved = KnownHosts::default();
+        assert!(Trust::Saved
+            .remember(&mut saved, "saved.example.org", 1965, b"saved")
+            .unwrap());
+        let trust = Trust::private();
+        assert!(!trust
+            .remember(&mut saved, "new.example.org", 1965, b"new")
+            .unwrap());
+        assert!(!saved.contains("new.example.org", 1965));
+        assert!(trust
+            .remember(&mut saved, "new.example.org", 1965, b"changed")
+            .is_err());
+        // Hosts which were saved before are still checked against
+        assert!(trust
+            .remember(&mut saved, "saved.example.org", 1965, b"changed")
+            .is_err());
+        // Each private window has its own hosts
+        assert!(!Trust::private()
+            .remember(&mut saved, "new.example.org", 1965, b"changed")
+            .unwrap());
+    }
+
+    #[test]
+    fn sha256_fingerprint() {
+        assert_eq!(
+            fingerprint(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Stand-ins for a Gemini server, for testing the client without a network
+//! or a TLS connection
+use std::{
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread::{self, JoinHandle},
+};
+
+/// A stream which replies with a fixed response and keeps the request
+pub struct MockStream {
+    input: Cursor<Vec<u8>>,
+    /// Returned by every read in place of the response, if set
+    error: Option<io::ErrorKind>,
+    pub output: Vec<u8>,
+}
+
+impl MockStream {
+    #[must_use]
+    pub fn new(input: impl Into<Vec<u8>>) -> Self {
+        Self {
+            input: Cursor::new(input.into()),
+            error: None,
+            output: vec![],
+        }
+    }
+
+    /// A stream whose connection fails with `kind` once the reply is read
+    #[must_use]
+    pub fn failing(kind: io::ErrorKind) -> Self {
+        Self {
+            error: Some(kind),
+            ..Self::new(vec![])
+        }
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.error {
+            Some(kind) => Err(kind.into()),
+            None => self.input.read(buf),
+        }
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Listens on a local port for a single request, which is answered with
+/// `reply`. The request line is returned when the thread is joined.
+/// # Panics
+/// Panics if unable to listen on a local port
+pub fn serve(reply: impl Into<Vec<u8>>) -> (SocketAddr, JoinHandle<String>) {
+    let reply = reply.into();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+        reader.get_mut().write_all(&reply).unwrap();
+        request
+    });
+    (addr, server)
+}
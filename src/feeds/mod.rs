@@ -0,0 +1,341 @@
+#![warn(clippy::all, clippy::pedantic)]
+use {
+    crate::{history::get_data_dir, storage},
+    chrono::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, error::Error, fmt::Write, path::PathBuf, sync::Mutex},
+    url::Url,
+};
+
+pub mod fetch;
+pub mod known_hosts;
+#[cfg(test)]
+pub mod mock;
+mod parse;
+
+pub use {
+    known_hosts::Trust,
+    parse::{parse, Feed, FeedEntry},
+};
+
+/// The most entries shown on `eva://feeds`
+const SHOWN_ENTRIES: usize = 200;
+
+#[must_use]
+pub fn get_feeds_file() -> PathBuf {
+    let mut feeds = get_data_dir();
+    feeds.push("feeds.toml");
+    feeds
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Subscription {
+    pub title: Option<String>,
+    /// When the feed was last fetched, whether or not that succeeded
+    #[serde(default)]
+    pub checked: Option<DateTime<Local>>,
+    /// Why the last attempt to fetch the feed failed, if it did
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    /// The url of the feed containing this entry
+    pub feed: String,
+    pub title: String,
+    pub published: DateTime<Local>,
+    #[serde(default)]
+    pub read: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Feeds {
+    /// key is the url of the feed
+    pub subscriptions: HashMap<String, Subscription>,
+    /// key is the url of the entry
+    pub entries: HashMap<String, Entry>,
+    /// The feeds file as it was last read or written, which is compared with
+    /// the file on disk to find changes made by another instance
+    #[serde(skip)]
+    saved: Option<toml::Value>,
+}
+
+impl Feeds {
+    /// Subscribes to the feed at `url`. Returns `false` if already subscribed.
+    pub fn subscribe(&mut self, url: &str, title: Option<&str>) -> bool {
+        if self.subscriptions.contains_key(url) {
+            return false;
+        }
+        let _old = self.subscriptions.insert(
+            String::from(url),
+            Subscription {
+                title: title.map(String::from),
+                ..Subscription::default()
+            },
+        );
+        true
+    }
+
+    /// Removes the subscription to `url` along with its entries
+    pub fn unsubscribe(&mut self, url: &str) {
+        let _old = self.subscriptions.remove(url);
+        self.entries.retain(|_, entry| entry.feed != url);
+    }
+
+    #[must_use]
+    pub fn is_subscribed(&self, url: &str) -> bool {
+        self.subscriptions.contains_key(url)
+    }
+
+    /// Records the result of fetching the feed at `url`. Entries which are no
+    /// longer in the feed are dropped, while those which remain keep their
+    /// read state.
+    pub fn apply(&mut self, url: &str, result: Result<Feed, String>) {
+        // The feed may have been unsubscribed from while it was being fetched
+        let subscription = match self.subscriptions.get_mut(url) {
+            Some(s) => s,
+            None => return,
+        };
+        subscription.checked = Some(Local::now());
+        match result {
+            Ok(feed) => {
+                subscription.error = None;
+                if feed.title.is_some() {
+                    subscription.title = feed.title;
+                }
+                let mut old = HashMap::new();
+                self.entries.retain(|entry_url, entry| {
+                    if entry.feed == url {
+                        let _read = old.insert(entry_url.clone(), entry.read);
+                        false
+                    } else {
+                        true
+                    }
+                });
+                for entry in feed.entries {
+                    let read = old.get(&entry.url).copied().unwrap_or(false);
+                    let _old = self.entries.insert(
+                        entry.url,
+                        Entry {
+                            feed: String::from(url),
+                            title: entry.title,
+                            published: entry.published,
+                            read,
+                        },
+                    );
+                }
+            }
+            Err(e) => subscription.error = Some(e),
+        }
+    }
+
+    /// Marks the entry for `url` as read. Returns `true` if it was unread.
+    pub fn mark_read(&mut self, url: &str) -> bool {
+        match self.entries.get_mut(url) {
+            Some(entry) if !entry.read => {
+                entry.read = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn mark_all_read(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.read = true;
+        }
+    }
+
+    #[must_use]
+    pub fn unread(&self) -> usize {
+        self.entries.values().filter(|e| !e.read).count()
+    }
+
+    fn feed_title<'a>(&'a self, url: &'a str) -> &'a str {
+        self.subscriptions
+            .get(url)
+            .and_then(|s| s.title.as_deref())
+            .unwrap_or(url)
+    }
+
+    /// Renders the newest entries across every feed, grouped by date, with
+    /// unread entries marked
+    #[must_use]
+    pub fn to_gmi(&self) -> String {
+        let mut page = String::from(
+            "# Feeds\n\n=> eva://feeds/refresh Refresh all feeds\n\
+            => eva://feeds/subscribe Subscribe to a feed\n\
+            => eva://feeds/read Mark all as read\n\
+            => eva://feeds/subscriptions Manage subscriptions\n",
+        );
+        if self.subscriptions.is_empty() {
+            page.push_str(
+                "\nYou are not subscribed to any feeds. Gemlogs which list their posts as \
+                dated links, and Atom feeds, can be subscribed to from the menu while viewing \
+                them.\n",
+            );
+            return page;
+        }
+        let _ = writeln!(page, "\n{} unread", self.unread());
+        let mut entries: Vec<(&String, &Entry)> = self.entries.iter().collect();
+        entries.sort_by(|(_, a), (_, b)| {
+            b.published
+                .cmp(&a.published)
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        let mut current_date = None;
+        for (url, entry) in entries.into_iter().take(SHOWN_ENTRIES) {
+            let date = entry.published.date_naive();
+            if current_date != Some(date) {
+                let _ = writeln!(page, "\n## {}\n", date.format("%Y-%m-%d"));
+                current_date = Some(date);
+            }
+            let _ = writeln!(
+                page,
+                "=> {url} {}{} - {}",
+                if entry.read { "" } else { "• " },
+                self.feed_title(&entry.feed),
+                entry.title,
+            );
+        }
+        page
+    }
+
+    #[must_use]
+    pub fn subscriptions_to_gmi(&self) -> String {
+        let mut page = String::from("# Subscriptions\n\n=> eva://feeds back\n");
+        let mut urls: Vec<&String> = self.subscriptions.keys().collect();
+        urls.sort_by_key(|url| self.feed_title(url).to_lowercase());
+        for url in urls {
+            let subscription = &self.subscriptions[url];
+            let _ = writeln!(page, "\n### {}\n=> {url}", self.feed_title(url));
+            match (&subscription.error, subscription.checked) {
+                (Some(e), _) => {
+                    let _ = writeln!(page, "Error: {e}");
+                }
+                (None, Some(checked)) => {
+                    let _ = writeln!(page, "Checked {}", checked.format("%Y-%m-%d %H:%M"));
+                }
+                (None, None) => page.push_str("Not checked yet\n"),
+            }
+            let _ = writeln!(
+                page,
+                "=> eva://feeds/unsubscribe?{} Unsubscribe",
+                urlencoding::encode(url),
+            );
+        }
+        page
+    }
+
+    /// Fetches every subscribed feed, or only the feed at `only`, and records
+    /// the results. The lock is only held between fetches, so this is meant
+    /// to be run on its own thread while the browser keeps using the feeds.
+    /// Servers are checked against the known hosts in `trust`.
+    /// # Panics
+    /// Panics if the mutex has been poisoned
+    pub fn update(feeds: &Mutex<Self>, only: Option<&str>, trust: &Trust) {
+        let urls: Vec<String> = feeds
+            .lock()
+            .unwrap()
+            .subscriptions
+            .keys()
+            .filter(|url| only.map_or(true, |only| only == url.as_str()))
+            .cloned()
+            .collect();
+        if urls.is_empty() {
+            return;
+        }
+        for url in urls {
+            let result = Self::fetch(&url, trust);
+            feeds.lock().unwrap().apply(&url, result);
+        }
+        if let Err(e) = feeds.lock().unwrap().save() {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn fetch(url: &str, trust: &Trust) -> Result<Feed, String> {
+        let url = Url::parse(url).map_err(|e| e.to_string())?;
+        let response = fetch::fetch(&url, trust).map_err(|e| e.to_string())?;
+        let feed = parse(&response.url, &response.mime, &response.body);
+        if feed.entries.is_empty() {
+            Err(String::from("No dated links or Atom entries were found"))
+        } else {
+            Ok(feed)
+        }
+    }
+
+    /// Writes the feeds to disk. If the file was changed by another instance
+    /// since it was last read or written, those changes are merged in first.
+    /// # Errors
+    /// Returns an error if unable to create the data directory, unable to
+    /// read the feeds file on disk, unable to serialize toml or unable to
+    /// write the toml to disk
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        let datadir = get_data_dir();
+        if !datadir.exists() {
+            std::fs::create_dir_all(&datadir)?;
+        }
+        let file = get_feeds_file();
+        let value = self.merge(storage::read_value(&file)?)?;
+        let toml_string = toml::to_string(&value)?;
+        storage::write(&file, toml_string)?;
+        self.saved = Some(value);
+        Ok(())
+    }
+
+    /// Merges the changes made to `theirs`, the feeds file on disk, by
+    /// another instance since it was last read or written here, returning
+    /// the feeds to write
+    fn merge(&mut self, theirs: Option<toml::Value>) -> Result<toml::Value, Box<dyn Error>> {
+        let ours = toml::Value::try_from(&*self)?;
+        match theirs {
+            Some(theirs) if self.saved.as_ref() != Some(&theirs) => {
+                let merged = storage::merge(self.saved.as_ref(), &ours, &theirs);
+                let feeds: Self = merged.clone().try_into()?;
+                self.subscriptions = feeds.subscriptions;
+                self.entries = feeds.entries;
+                Ok(merged)
+            }
+            _ => Ok(ours),
+        }
+    }
+
+    /// # Errors
+    /// Returns an error if unable to read the feeds file or deserialize toml
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        let value = match storage::read_value(&get_feeds_file())? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let mut feeds: Self = value.clone().try_into()?;
+        feeds.saved = Some(value);
+        Ok(Some(feeds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_merges_changes_from_another_instance() {
+        let mut base = Feeds::default();
+        let _new = base.subscribe("gemini://a.example.org/", None);
+        let base_value = toml::Value::try_from(&base).unwrap();
+        let mut ours = base.clone();
+        ours.saved = Some(base_value);
+        let _new = ours.subscribe("gemini://b.example.org/", None);
+        let mut theirs = base;
+        let _new = theirs.subscribe("gemini://c.example.org/", None);
+        theirs.unsubscribe("gemini://a.example.org/");
+        let merged = ours
+            .merge(Some(toml::Value::try_from(&theirs).unwrap()))
+            .unwrap();
+        assert!(!ours.is_subscribed("gemini://a.example.org/"));
+        assert!(ours.is_subscribed("gemini://b.example.org/"));
+        assert!(ours.is_subscribed("gemini://c.example.org/"));
+        assert_eq!(merged, toml::Value::try_from(&ours).unwrap());
+    }
+}
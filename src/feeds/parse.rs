@@ -0,0 +1,275 @@
+//! Reading the entries of a feed, either from a gemtext page following the
+//! Gemini subscription convention or from an Atom feed
+use {chrono::prelude::*, url::Url};
+
+/// A single post found in a feed
+#[derive(Clone, Debug)]
+pub struct FeedEntry {
+    pub url: String,
+    pub title: String,
+    pub published: DateTime<Local>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    pub title: Option<String>,
+    pub entries: Vec<FeedEntry>,
+}
+
+/// Parses `body`, deciding between gemtext and Atom from its mime type, or
+/// from the body itself if the mime type is not conclusive. Relative links
+/// are resolved against `base`.
+#[must_use]
+pub fn parse(base: &Url, mime: &str, body: &str) -> Feed {
+    let mime = mime.to_lowercase();
+    if mime.starts_with("text/gemini") {
+        parse_gemtext(base, body)
+    } else if mime.contains("xml") || body.trim_start().starts_with('<') {
+        parse_atom(base, body)
+    } else {
+        parse_gemtext(base, body)
+    }
+}
+
+/// Parses a date such as `2022-11-05` at the start of `text`, returning it
+/// along with the rest of the text
+fn leading_date(text: &str) -> Option<(NaiveDate, &str)> {
+    let date = text.get(..10)?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some((date, &text[10..]))
+}
+
+/// The feed's title is its first top level heading, and each link whose
+/// label starts with a date is an entry
+fn parse_gemtext(base: &Url, body: &str) -> Feed {
+    let mut feed = Feed::default();
+    for line in body.lines() {
+        if let Some(heading) = line.strip_prefix("# ") {
+            if feed.title.is_none() {
+                feed.title = Some(String::from(heading.trim()));
+            }
+            continue;
+        }
+        let link = match line.strip_prefix("=>") {
+            Some(link) => link.trim(),
+            None => continue,
+        };
+        let (url, label) = match link.split_once(char::is_whitespace) {
+            Some((url, label)) => (url, label.trim()),
+            None => continue,
+        };
+        let (date, title) = match leading_date(label) {
+            Some(d) => d,
+            None => continue,
+        };
+        let url = match base.join(url) {
+            Ok(u) => u,
+            Err(_) => continue,
+        };
+        let published = match Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+            .earliest()
+        {
+            Some(p) => p,
+            None => continue,
+        };
+        let title = title.trim_start_matches(|c: char| c.is_whitespace() || c == '-' || c == ':');
+        feed.entries.push(FeedEntry {
+            url: url.to_string(),
+            title: String::from(if title.is_empty() {
+                url.as_str()
+            } else {
+                title
+            }),
+            published,
+        });
+    }
+    feed
+}
+
+/// Unescapes the text of an element, which is also put onto a single line as
+/// it ends up in a gemtext link
+fn unescape_markup(text: &str) -> String {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+        .unwrap_or(text);
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Returns the contents of the first `name` element in `text`, which may
+/// have attributes, ignoring case
+fn element<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let lower = text.to_ascii_lowercase();
+    let open = format!("<{name}");
+    let mut pos = 0;
+    loop {
+        let start = pos + lower[pos..].find(&open)?;
+        let after = start + open.len();
+        match lower[after..].chars().next()? {
+            '>' => {
+                let end = after + 1 + lower[after + 1..].find(&format!("</{name}>"))?;
+                return Some(&text[after + 1..end]);
+            }
+            c if c.is_whitespace() => {
+                let close = after + lower[after..].find('>')?;
+                let end = close + 1 + lower[close + 1..].find(&format!("</{name}>"))?;
+                return Some(&text[close + 1..end]);
+            }
+            _ => pos = after,
+        }
+    }
+}
+
+/// Finds `name="value"` within a markup tag, ignoring the case of `name`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    for quote in ['"', '\''] {
+        if let Some(start) = lower.find(&format!(" {name}={quote}")) {
+            let start = start + name.len() + 3;
+            let end = start + tag[start..].find(quote)?;
+            return Some(unescape_markup(&tag[start..end]));
+        }
+    }
+    None
+}
+
+/// Returns the link of an Atom entry, preferring the alternate link
+fn entry_link(entry: &str) -> Option<String> {
+    let lower = entry.to_ascii_lowercase();
+    let mut links = vec![];
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<link").map(|i| pos + i) {
+        let end = start + lower[start..].find('>')?;
+        links.push(&entry[start..end]);
+        pos = end;
+    }
+    links
+        .iter()
+        .find(|tag| matches!(attribute(tag, "rel").as_deref(), None | Some("alternate")))
+        .or_else(|| links.first())
+        .and_then(|tag| attribute(tag, "href"))
+}
+
+fn parse_atom(base: &Url, body: &str) -> Feed {
+    let lower = body.to_ascii_lowercase();
+    let head_end = lower.find("<entry").unwrap_or(body.len());
+    let mut feed = Feed {
+        title: element(&body[..head_end], "title").map(unescape_markup),
+        entries: vec![],
+    };
+    let mut pos = head_end;
+    while let Some(start) = lower[pos..].find("<entry").map(|i| pos + i) {
+        let end = lower[start..]
+            .find("</entry>")
+            .map_or(body.len(), |i| start + i);
+        let entry = &body[start..end];
+        pos = end;
+        let url = match entry_link(entry).and_then(|l| base.join(&l).ok()) {
+            Some(u) => u,
+            None => continue,
+        };
+        let published = element(entry, "updated")
+            .or_else(|| element(entry, "published"))
+            .and_then(|d| DateTime::parse_from_rfc3339(d.trim()).ok())
+            .map(|d| d.with_timezone(&Local));
+        let published = match published {
+            Some(p) => p,
+            None => continue,
+        };
+        feed.entries.push(FeedEntry {
+            title: element(entry, "title")
+                .map(unescape_markup)
+                .filter(|t| !t.is_empty())
+                .unwrap_or_else(|| url.to_string()),
+            url: url.to_string(),
+            published,
+        });
+    }
+    feed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("gemini://example.org/gemlog/").unwrap()
+    }
+
+    #[test]
+    fn gemtext() {
+        let body = "# My gemlog\n\
+            Some text\n\
+            => /about.gmi About me\n\
+            => 2022-11-05-post.gmi 2022-11-05 - A post\n\
+            => gemini://other.example.org/ 2022-10-01\n\
+            ## Not the title\n";
+        let feed = parse(&base(), "text/gemini", body);
+        assert_eq!(feed.title.as_deref(), Some("My gemlog"));
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(
+            feed.entries[0].url,
+            "gemini://example.org/gemlog/2022-11-05-post.gmi"
+        );
+        assert_eq!(feed.entries[0].title, "A post");
+        assert_eq!(
+            feed.entries[0].published.date_naive(),
+            NaiveDate::from_ymd_opt(2022, 11, 5).unwrap()
+        );
+        // Undated links are skipped and untitled ones are named by their url
+        assert_eq!(feed.entries[1].title, "gemini://other.example.org/");
+    }
+
+    #[test]
+    fn atom() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Fish &amp; chips</title>
+  <entry>
+    <title><![CDATA[First <post>]]></title>
+    <link rel="self" href="/self.xml"/>
+    <link rel="alternate" href="first.gmi"/>
+    <updated>2022-11-05T10:00:00Z</updated>
+  </entry>
+  <entry>
+    <title type="text">Second</title>
+    <link href='gemini://other.example.org/second.gmi'/>
+    <published>2022-11-06T10:00:00+01:00</published>
+  </entry>
+  <entry>
+    <title>No date</title>
+    <link href="third.gmi"/>
+  </entry>
+</feed>"#;
+        let feed = parse(&base(), "application/atom+xml", body);
+        assert_eq!(feed.title.as_deref(), Some("Fish & chips"));
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(feed.entries[0].title, "First <post>");
+        assert_eq!(feed.entries[0].url, "gemini://example.org/gemlog/first.gmi");
+        assert_eq!(
+            feed.entries[0].published,
+            Utc.with_ymd_and_hms(2022, 11, 5, 10, 0, 0).unwrap()
+        );
+        assert_eq!(feed.entries[1].title, "Second");
+        assert_eq!(feed.entries[1].url, "gemini://other.example.org/second.gmi");
+    }
+
+    #[test]
+    fn detect_format() {
+        let atom = "<feed><entry><link href=\"a.gmi\"/>\
+            <updated>2022-11-05T10:00:00Z</updated></entry></feed>";
+        assert_eq!(parse(&base(), "text/plain", atom).entries.len(), 1);
+        let gemtext = "=> a.gmi 2022-11-05 A post";
+        assert_eq!(parse(&base(), "", gemtext).entries.len(), 1);
+    }
+}
@@ -3,15 +3,17 @@ use {
     crate::keys::Keys,
     gtk::{
         gio::SimpleAction,
-        glib::{self, clone},
+        glib::{self, clone, VariantTy},
         prelude::*,
     },
     std::rc::Rc,
 };
 
-const ACTIONS: [&str; 27] = [
+const ACTIONS: [&str; 38] = [
     "new_tab",
     "close_tab",
+    "reopen_closed_tab",
+    "reopen_closed_window",
     "next_tab",
     "prev_tab",
     "tab1",
@@ -28,10 +30,19 @@ const ACTIONS: [&str; 27] = [
     "go_previous",
     "go_next",
     "new_window",
+    "new_private_window",
     "open_bookmarks",
     "bookmark_page",
+    "export_bookmarks",
+    "import_bookmarks",
     "open_history",
     "clear_history",
+    "export_history",
+    "import_history",
+    "open_sessions",
+    "save_session",
+    "open_feeds",
+    "subscribe",
     "view_source",
     "save_page",
     "open_prefs",
@@ -39,11 +50,24 @@ const ACTIONS: [&str; 27] = [
     "quit",
 ];
 
+/// Sets the keyboard shortcut for each action, which applies to every window
+pub fn set_accels(app: &gtk::Application, keys: &Keys) {
+    for name in &ACTIONS {
+        app.set_accels_for_action(&format!("win.{}", name), &[keys.get(name)]);
+    }
+}
+
 pub fn add(gui: &Rc<Gui>, app: &gtk::Application) {
-    let keys = Keys::from_file().unwrap_or_default();
+    let keys = match Keys::from_file() {
+        Ok(k) => k.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            Keys::default()
+        }
+    };
+    set_accels(app, &keys);
     for name in &ACTIONS {
         let action = SimpleAction::new(name, None);
-        app.set_accels_for_action(&format!("win.{}", name), &[keys.get(name)]);
         gui.window.add_action(&action);
         match *name {
             "new_tab" => {
@@ -56,6 +80,16 @@ pub fn add(gui: &Rc<Gui>, app: &gtk::Application) {
                     gui.close_current_tab();
                 }));
             }
+            "reopen_closed_tab" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.reopen_closed_tab();
+                }));
+            }
+            "reopen_closed_window" => {
+                action.connect_activate(clone!(@strong app => move |_,_| {
+                    crate::gui::reopen_closed_window(&app);
+                }));
+            }
             "next_tab" => {
                 action.connect_activate(clone!(@weak gui => move |_,_| {
                     gui.next_tab();
@@ -141,7 +175,13 @@ pub fn add(gui: &Rc<Gui>, app: &gtk::Application) {
             }
             "new_window" => {
                 action.connect_activate(clone!(@weak gui, @strong app => move |_,_| {
-                    let new_gui = crate::gui::build_ui(&app);
+                    let new_gui = crate::gui::build_ui(&app, gui.is_private());
+                    new_gui.new_tab(None);
+                }));
+            }
+            "new_private_window" => {
+                action.connect_activate(clone!(@strong app => move |_,_| {
+                    let new_gui = crate::gui::build_ui(&app, true);
                     new_gui.new_tab(None);
                 }));
             }
@@ -157,14 +197,56 @@ pub fn add(gui: &Rc<Gui>, app: &gtk::Application) {
                     }
                 }));
             }
+            "export_bookmarks" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.export_bookmarks();
+                }));
+            }
+            "import_bookmarks" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.import_bookmarks();
+                }));
+            }
             "open_history" => {
                 action.connect_activate(clone!(@weak gui => move |_,_| {
-                    println!("Not implemented yet");
+                    gui.open_history();
                 }));
             }
             "clear_history" => {
                 action.connect_activate(clone!(@weak gui => move |_,_| {
-                    println!("Not implemented yet");
+                    gui.clear_history();
+                }));
+            }
+            "export_history" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.export_history();
+                }));
+            }
+            "import_history" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.import_history();
+                }));
+            }
+            "open_sessions" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.open_sessions();
+                }));
+            }
+            "save_session" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    if let Some(tab) = gui.current_tab() {
+                        tab.request_session_name();
+                    }
+                }));
+            }
+            "open_feeds" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.open_feeds();
+                }));
+            }
+            "subscribe" => {
+                action.connect_activate(clone!(@weak gui => move |_,_| {
+                    gui.subscribe();
                 }));
             }
             "view_source" => {
@@ -197,4 +279,40 @@ pub fn add(gui: &Rc<Gui>, app: &gtk::Application) {
             _ => {}
         }
     }
+    // These take the name of the session as a parameter, and are activated
+    // from the links on eva://sessions
+    let action = SimpleAction::new("save_named_session", Some(VariantTy::STRING));
+    action.connect_activate(clone!(@weak gui => move |_,param| {
+        if let Some(name) = param.and_then(|p| p.get::<String>()) {
+            gui.save_named_session(&name);
+        }
+    }));
+    gui.window.add_action(&action);
+    let action = SimpleAction::new("open_named_session", Some(VariantTy::STRING));
+    action.connect_activate(clone!(@strong app => move |_,param| {
+        if let Some(name) = param.and_then(|p| p.get::<String>()) {
+            crate::gui::open_named_session(&app, &name);
+        }
+    }));
+    gui.window.add_action(&action);
+    // Activated from eva://feeds and eva://bookmarks, so that the servers
+    // contacted are trusted according to whether this window is private. An
+    // empty parameter fetches every feed.
+    let action = SimpleAction::new("update_feeds", Some(VariantTy::STRING));
+    action.connect_activate(clone!(@weak gui => move |_,param| {
+        let only = param
+            .and_then(|p| p.get::<String>())
+            .filter(|feed| !feed.is_empty());
+        gui.update_feeds(only);
+    }));
+    gui.window.add_action(&action);
+    let action = SimpleAction::new("check_bookmarks", None);
+    action.connect_activate(clone!(@weak gui => move |_,_| {
+        gui.check_bookmarks();
+    }));
+    gui.window.add_action(&action);
+    // Activated by the bookmark editor and eva://bookmarks after a change
+    let action = SimpleAction::new("refresh_bookmarks", None);
+    action.connect_activate(|_, _| crate::gui::refresh_bookmarks());
+    gui.window.add_action(&action);
 }
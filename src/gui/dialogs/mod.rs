@@ -25,12 +25,14 @@ impl Dialogs {
             ],
         );
         about.add_credit_section("Serde written by", &["David Tolnay", "and others"]);
+        about.add_credit_section("Serde_json written by", &["David Tolnay", "and others"]);
         about.add_credit_section("Chrono written by", &["Kang Seonghoon", "and others"]);
         about.add_credit_section("Url written by", &["The Servo project"]);
         about.add_credit_section("Lazy-static by", &["Marvin Löbel", "and others"]);
         about.add_credit_section("Fastrand by", &["Taiki Endo", "and others"]);
         about.add_credit_section("Urlencoding by", &["Kornel", "Bertram Truong"]);
         about.add_credit_section("Mime2ext by", &["Jan Verbeek", "and others"]);
+        about.add_credit_section("Native-tls by", &["Steven Fackler", "and others"]);
         about.add_credit_section("Gemview by", &["Nathan Fisher"]);
         about.add_credit_section("Mime-open by", &["Nathan Fisher"]);
         about.add_credit_section("Rgba-simple by", &["Nathan Fisher"]);
@@ -71,6 +73,27 @@ impl Dialogs {
         dlg
     }
 
+    /// Creates a dialog for choosing a file to import from or export to. A new
+    /// dialog is used each time so that response handlers do not accumulate.
+    pub fn file_chooser(
+        window: &gtk::ApplicationWindow,
+        title: &str,
+        action: gtk::FileChooserAction,
+    ) -> gtk::FileChooserDialog {
+        let dlg = gtk::FileChooserDialog::builder()
+            .use_header_bar(1)
+            .destroy_with_parent(true)
+            .modal(true)
+            .transient_for(window)
+            .title(title)
+            .action(action)
+            .create_folders(true)
+            .build();
+        dlg.add_button("Accept", gtk::ResponseType::Accept);
+        dlg.add_button("Cancel", gtk::ResponseType::Cancel);
+        dlg
+    }
+
     fn init_preferences(window: &gtk::ApplicationWindow) -> Prefs {
         let dlg = Prefs::new();
         dlg.load_config();
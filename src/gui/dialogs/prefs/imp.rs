@@ -23,6 +23,18 @@ pub struct Prefs {
     #[template_child]
     pub download_location: TemplateChild<gtk::Button>,
     #[template_child]
+    pub theme: TemplateChild<gtk::ComboBoxText>,
+    #[template_child]
+    pub theme_import: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub theme_export: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub follow_desktop: TemplateChild<gtk::Switch>,
+    #[template_child]
+    pub light_theme: TemplateChild<gtk::ComboBoxText>,
+    #[template_child]
+    pub dark_theme: TemplateChild<gtk::ComboBoxText>,
+    #[template_child]
     pub fg_color: TemplateChild<gtk::ColorButton>,
     #[template_child]
     pub bg_color: TemplateChild<gtk::ColorButton>,
@@ -50,6 +62,16 @@ pub struct Prefs {
     pub h2_font: TemplateChild<gtk::FontButton>,
     #[template_child]
     pub h3_font: TemplateChild<gtk::FontButton>,
+    #[template_child]
+    pub history_max_age: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub history_max_entries: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub history_clear_on_exit: TemplateChild<gtk::Switch>,
+    #[template_child]
+    pub bookmark_sort: TemplateChild<gtk::ComboBoxText>,
+    #[template_child]
+    pub bookmark_group_by_tag: TemplateChild<gtk::Switch>,
 }
 
 #[glib::object_subclass]
@@ -3,7 +3,8 @@ mod imp;
 use {
     crate::{
         config::{
-            Colors, Config, DownloadScheme, Font, Fonts, General, NewPage, ShowTabs, TabPosition,
+            Appearance, BookmarkSort, BookmarkView, Colors, Config, DownloadScheme, Font, Fonts,
+            General, History, NewPage, ShowTabs, TabPosition, Theme,
         },
         CONFIG,
     },
@@ -30,8 +31,7 @@ impl Default for Prefs {
 
 impl Prefs {
     pub fn new() -> Self {
-        let dlg: Self =
-            Object::new(&[("use-header-bar", &1.to_value())]);
+        let dlg: Self = Object::new(&[("use-header-bar", &1.to_value())]);
         let dialog = dlg.clone();
         dlg.imp().download_scheme.connect_changed(move |_| {
             if let Some(scheme) = dialog.download_scheme() {
@@ -57,6 +57,49 @@ impl Prefs {
             }
             dlg.hide();
         });
+        let dialog = dlg.clone();
+        dlg.imp().theme.connect_changed(move |combo| {
+            if let Some(theme) = combo.active_id().and_then(|name| Theme::find(&name)) {
+                dialog.set_colors(&theme.colors);
+                dialog.set_fonts(&theme.fonts);
+            }
+        });
+        let imp = dlg.imp();
+        for button in [
+            &imp.fg_color,
+            &imp.bg_color,
+            &imp.pre_fg_color,
+            &imp.pre_bg_color,
+            &imp.quote_fg_color,
+            &imp.quote_bg_color,
+            &imp.link_color,
+            &imp.hover_color,
+        ] {
+            let dialog = dlg.clone();
+            button.connect_color_set(move |_| dialog.select_theme());
+        }
+        for button in [
+            &imp.pg_font,
+            &imp.pre_font,
+            &imp.quote_font,
+            &imp.h1_font,
+            &imp.h2_font,
+            &imp.h3_font,
+        ] {
+            let dialog = dlg.clone();
+            button.connect_font_set(move |_| dialog.select_theme());
+        }
+        let dialog = dlg.clone();
+        imp.theme_import
+            .connect_clicked(move |_| dialog.import_theme());
+        let dialog = dlg.clone();
+        imp.theme_export
+            .connect_clicked(move |_| dialog.export_theme());
+        let dialog = dlg.clone();
+        imp.follow_desktop.connect_active_notify(move |switch| {
+            dialog.imp().light_theme.set_sensitive(switch.is_active());
+            dialog.imp().dark_theme.set_sensitive(switch.is_active());
+        });
         dlg
     }
 
@@ -73,6 +116,7 @@ impl Prefs {
             match id.as_str() {
                 "home" => Some(NewPage::Home),
                 "blank" => Some(NewPage::Blank),
+                "session" => Some(NewPage::LastSession),
                 _ => None,
             }
         } else {
@@ -84,6 +128,7 @@ impl Prefs {
         self.imp().new_page.set_active_id(match page {
             NewPage::Home => Some("home"),
             NewPage::Blank => Some("blank"),
+            NewPage::LastSession => Some("session"),
         });
     }
 
@@ -214,6 +259,121 @@ impl Prefs {
         });
     }
 
+    /// Fills the theme lists with the built in themes and the user's own,
+    /// keeping the light and dark themes which were chosen
+    fn load_themes(&self) {
+        let imp = self.imp();
+        let light = imp.light_theme.active_id();
+        let dark = imp.dark_theme.active_id();
+        let themes = Theme::all();
+        for combo in [&imp.theme, &imp.light_theme, &imp.dark_theme] {
+            combo.remove_all();
+            for theme in &themes {
+                combo.append(Some(theme.name.as_str()), &theme.name);
+            }
+        }
+        imp.light_theme.set_active_id(light.as_deref());
+        imp.dark_theme.set_active_id(dark.as_deref());
+    }
+
+    /// Shows the theme which the colors and fonts in the dialog come from,
+    /// or none if they have been changed from any theme
+    fn select_theme(&self) {
+        let colors = self.colors();
+        let theme = self.fonts().and_then(|fonts| {
+            Theme::all()
+                .into_iter()
+                .find(|theme| theme.matches(&colors, &fonts))
+        });
+        self.imp()
+            .theme
+            .set_active_id(theme.as_ref().map(|t| t.name.as_str()));
+    }
+
+    fn theme_chooser(&self, title: &str, action: gtk::FileChooserAction) -> gtk::FileChooserDialog {
+        let chooser = gtk::FileChooserDialog::builder()
+            .use_header_bar(1)
+            .modal(true)
+            .title(title)
+            .transient_for(self)
+            .action(action)
+            .build();
+        chooser.add_button("_Cancel", gtk::ResponseType::Cancel);
+        chooser.add_button("Accept", gtk::ResponseType::Accept);
+        chooser
+    }
+
+    /// Asks for a theme file and adds it to the user's themes, then fills in
+    /// its colors and fonts
+    fn import_theme(&self) {
+        let chooser = self.theme_chooser("Import theme", gtk::FileChooserAction::Open);
+        let dialog = self.clone();
+        chooser.connect_response(move |chooser, res| {
+            if res == gtk::ResponseType::Accept {
+                if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                    match Theme::import(&path) {
+                        Ok(theme) => {
+                            dialog.load_themes();
+                            dialog.imp().theme.set_active_id(Some(theme.name.as_str()));
+                        }
+                        Err(e) => dialog.show_error(&format!("Unable to import theme: {}", e)),
+                    }
+                }
+            }
+            chooser.destroy();
+        });
+        chooser.show();
+    }
+
+    /// Asks for a file and saves the colors and fonts in the dialog to it as
+    /// a theme. The theme takes the name of the selected theme, or else the
+    /// name of the file.
+    fn export_theme(&self) {
+        let chooser = self.theme_chooser("Export theme", gtk::FileChooserAction::Save);
+        let selected = self.imp().theme.active_id().map(|id| id.to_string());
+        chooser.set_current_name(&format!(
+            "{}.toml",
+            selected.as_deref().unwrap_or("theme").to_lowercase()
+        ));
+        let dialog = self.clone();
+        chooser.connect_response(move |chooser, res| {
+            if res == gtk::ResponseType::Accept {
+                if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                    let name = selected.clone().or_else(|| {
+                        path.file_stem()
+                            .map(|stem| stem.to_string_lossy().to_string())
+                    });
+                    let result = match (name, dialog.fonts()) {
+                        (Some(name), Some(fonts)) => Theme {
+                            name,
+                            colors: dialog.colors(),
+                            fonts,
+                        }
+                        .export(&path),
+                        _ => Err(String::from("Incomplete theme").into()),
+                    };
+                    if let Err(e) = result {
+                        dialog.show_error(&format!("Unable to export theme: {}", e));
+                    }
+                }
+            }
+            chooser.destroy();
+        });
+        chooser.show();
+    }
+
+    fn show_error(&self, message: &str) {
+        let dlg = gtk::MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .message_type(gtk::MessageType::Error)
+            .buttons(gtk::ButtonsType::Close)
+            .text(message)
+            .build();
+        dlg.connect_response(|dlg, _| dlg.close());
+        dlg.show();
+    }
+
     pub fn fg_color(&self) -> RGBA<u8> {
         RGBA::from(self.imp().fg_color.rgba())
     }
@@ -388,6 +548,97 @@ impl Prefs {
         self.set_h3_font(&fonts.h3);
     }
 
+    pub fn history_max_age(&self) -> u32 {
+        u32::try_from(self.imp().history_max_age.value_as_int()).unwrap_or_default()
+    }
+
+    pub fn set_history_max_age(&self, days: u32) {
+        self.imp().history_max_age.set_value(f64::from(days));
+    }
+
+    pub fn history_max_entries(&self) -> u32 {
+        u32::try_from(self.imp().history_max_entries.value_as_int()).unwrap_or_default()
+    }
+
+    pub fn set_history_max_entries(&self, entries: u32) {
+        self.imp().history_max_entries.set_value(f64::from(entries));
+    }
+
+    pub fn history_clear_on_exit(&self) -> bool {
+        self.imp().history_clear_on_exit.is_active()
+    }
+
+    pub fn set_history_clear_on_exit(&self, clear: bool) {
+        self.imp().history_clear_on_exit.set_active(clear);
+    }
+
+    pub fn history(&self) -> History {
+        History {
+            max_age: self.history_max_age(),
+            max_entries: self.history_max_entries(),
+            clear_on_exit: self.history_clear_on_exit(),
+        }
+    }
+
+    pub fn set_history(&self, history: &History) {
+        self.set_history_max_age(history.max_age);
+        self.set_history_max_entries(history.max_entries);
+        self.set_history_clear_on_exit(history.clear_on_exit);
+    }
+
+    pub fn bookmark_sort(&self) -> BookmarkSort {
+        self.imp()
+            .bookmark_sort
+            .active_id()
+            .and_then(|id| BookmarkSort::from_name(id.as_str()))
+            .unwrap_or_default()
+    }
+
+    pub fn set_bookmark_sort(&self, sort: BookmarkSort) {
+        self.imp().bookmark_sort.set_active_id(Some(sort.name()));
+    }
+
+    pub fn bookmarks(&self) -> BookmarkView {
+        BookmarkView {
+            sort: self.bookmark_sort(),
+            group_by_tag: self.imp().bookmark_group_by_tag.is_active(),
+        }
+    }
+
+    pub fn set_bookmarks(&self, bookmarks: &BookmarkView) {
+        self.set_bookmark_sort(bookmarks.sort);
+        self.imp()
+            .bookmark_group_by_tag
+            .set_active(bookmarks.group_by_tag);
+    }
+
+    pub fn appearance(&self) -> Appearance {
+        let imp = self.imp();
+        let default = Appearance::default();
+        Appearance {
+            follow_desktop: imp.follow_desktop.is_active(),
+            light_theme: imp
+                .light_theme
+                .active_id()
+                .map_or(default.light_theme, |id| id.to_string()),
+            dark_theme: imp
+                .dark_theme
+                .active_id()
+                .map_or(default.dark_theme, |id| id.to_string()),
+        }
+    }
+
+    pub fn set_appearance(&self, appearance: &Appearance) {
+        let imp = self.imp();
+        imp.follow_desktop.set_active(appearance.follow_desktop);
+        imp.light_theme.set_sensitive(appearance.follow_desktop);
+        imp.dark_theme.set_sensitive(appearance.follow_desktop);
+        imp.light_theme
+            .set_active_id(Some(appearance.light_theme.as_str()));
+        imp.dark_theme
+            .set_active_id(Some(appearance.dark_theme.as_str()));
+    }
+
     pub fn config(&self) -> Option<Config> {
         Some(Config {
             general: match self.general() {
@@ -399,6 +650,11 @@ impl Prefs {
                 Some(f) => f,
                 None => return None,
             },
+            history: self.history(),
+            bookmarks: self.bookmarks(),
+            appearance: self.appearance(),
+            // Capsule styles are only edited in config.toml
+            capsules: CONFIG.lock().unwrap().capsules.clone(),
         })
     }
 
@@ -407,6 +663,13 @@ impl Prefs {
         self.set_general(&cfg.general);
         self.set_colors(&cfg.colors);
         self.set_fonts(&cfg.fonts);
+        self.set_history(&cfg.history);
+        self.set_bookmarks(&cfg.bookmarks);
+        let appearance = cfg.appearance.clone();
+        drop(cfg);
+        self.load_themes();
+        self.select_theme();
+        self.set_appearance(&appearance);
     }
 
     fn init_dl_location(&self) -> gtk::FileChooserDialog {
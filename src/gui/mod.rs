@@ -1,32 +1,93 @@
 #![allow(clippy::too_many_lines)]
 mod actions;
 mod dialogs;
+mod scheme;
 pub mod tab;
 pub mod uri;
 use {
-    crate::{config, CONFIG},
+    crate::{
+        bookmarks, config, feeds, history, keys, session, storage, BOOKMARKS, CONFIG, FEEDS,
+        HISTORY, SEARCH, SESSIONS,
+    },
+    chrono::{DateTime, Local},
     dialogs::Dialogs,
     gemview::GemView,
     gtk::{
         gdk::Display,
-        gio::{Cancellable, Notification},
+        gio::{self, Cancellable, Notification},
         glib,
         glib::{char::Char, clone, OptionArg, OptionFlags},
         prelude::*,
         Application, CssProvider, ResponseType, StyleContext,
     },
+    lazy_static::lazy_static,
     mime2ext::mime2ext,
-    std::{borrow::Cow, cell::RefCell, collections::HashMap, fs, path::PathBuf, rc::Rc},
-    tab::Tab,
+    std::{
+        borrow::Cow,
+        cell::RefCell,
+        collections::HashMap,
+        error::Error,
+        fs,
+        path::{Path, PathBuf},
+        rc::{Rc, Weak},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    },
+    tab::{Navigation, Tab},
     url::Url,
 };
 
+/// The number of closed tabs and windows which are remembered
+const MAX_CLOSED: usize = 25;
+
+/// How often, in seconds, the session is saved while Eva is running
+const AUTOSAVE_INTERVAL: u32 = 60;
+
+/// How often, in seconds, feeds are refreshed while Eva is running
+const FEED_INTERVAL: u32 = 60 * 60;
+
+/// Set while every feed is being refreshed, so that refreshes do not pile up
+static UPDATING_FEEDS: AtomicBool = AtomicBool::new(false);
+
+/// Set while the bookmarks are being checked for broken links
+static CHECKING_BOOKMARKS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Every open window, used when saving the session
+    static WINDOWS: RefCell<Vec<Weak<Gui>>> = RefCell::new(Vec::new());
+    /// Watches the files which another instance of Eva may change, kept here
+    /// so that they are not dropped
+    static MONITORS: RefCell<Vec<gio::FileMonitor>> = RefCell::new(Vec::new());
+    /// The style sheet holding the configured colors, which is shared by every
+    /// window and replaced when the colors change
+    static CSS: CssProvider = {
+        let provider = CssProvider::new();
+        StyleContext::add_provider_for_display(
+            &Display::default().expect("Cannot connect to display"),
+            &provider,
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
+        );
+        provider
+    };
+}
+
+lazy_static! {
+    /// The tabs of recently closed windows, most recent last
+    static ref CLOSED_WINDOWS: Mutex<Vec<Vec<Navigation>>> = Mutex::new(Vec::new());
+}
+
 #[derive(Clone)]
 pub struct Gui {
     window: gtk::ApplicationWindow,
     notebook: gtk::Notebook,
-    tabs: RefCell<HashMap<String, Tab>>,
+    tabs: Rc<RefCell<HashMap<String, Tab>>>,
+    closed_tabs: Rc<RefCell<Vec<Navigation>>>,
     dialogs: Dialogs,
+    /// The certificates of capsules first contacted in the background from
+    /// this window, if it is private
+    known_hosts: Arc<Mutex<feeds::known_hosts::KnownHosts>>,
 }
 
 impl Default for Gui {
@@ -34,26 +95,69 @@ impl Default for Gui {
         let builder = gtk::Builder::from_string(include_str!("main.ui"));
         let window: gtk::ApplicationWindow = builder.object("mainWindow").unwrap();
         let notebook: gtk::Notebook = builder.object("mainNotebook").unwrap();
-        let tabs: RefCell<HashMap<String, Tab>> = RefCell::new(HashMap::new());
+        let tabs: Rc<RefCell<HashMap<String, Tab>>> = Rc::new(RefCell::new(HashMap::new()));
+        let closed_tabs: Rc<RefCell<Vec<Navigation>>> = Rc::new(RefCell::new(Vec::new()));
         let dialogs: Dialogs = Dialogs::init(&window);
 
         Self {
             window,
             notebook,
             tabs,
+            closed_tabs,
             dialogs,
+            known_hosts: Arc::default(),
         }
     }
 }
 
+/// Adds a closed tab's back/forward list to `closed`, unless the tab was blank
+fn remember_closed(closed: &RefCell<Vec<Navigation>>, nav: Navigation) {
+    if nav.current().is_none() {
+        return;
+    }
+    let mut closed = closed.borrow_mut();
+    closed.push(nav);
+    if closed.len() > MAX_CLOSED {
+        closed.remove(0);
+    }
+}
+
 impl Gui {
+    /// Marks this window as private. Private windows do not record history,
+    /// ask before writing anything else to disk and only trust the capsules
+    /// they contact in memory.
+    fn set_private(&self) {
+        self.window.add_css_class("private");
+        set_title(&self.window, "<blank>");
+    }
+
+    fn is_private(&self) -> bool {
+        self.window.has_css_class("private")
+    }
+
+    /// Where the certificates of capsules contacted in the background are
+    /// kept, which is only in memory for a private window
+    fn trust(&self) -> feeds::Trust {
+        if self.is_private() {
+            feeds::Trust::Private(self.known_hosts.clone())
+        } else {
+            feeds::Trust::Saved
+        }
+    }
+
+    /// Fetches the subscribed feeds, or only the feed at `only`
+    fn update_feeds(&self, only: Option<String>) {
+        update_feeds(only, self.trust());
+    }
+
+    fn check_bookmarks(&self) {
+        check_bookmarks(self.trust());
+    }
+
     fn new_tab(&self, uri: Option<&str>) {
-        let newtab = tab::Tab::init();
-        self.tabs
-            .borrow_mut()
-            .insert(newtab.tab().widget_name().to_string(), newtab.clone());
+        let newtab = self.add_tab();
         let cfg = CONFIG.lock().unwrap().clone();
-        let uri = if cfg.general.new_page == config::NewPage::Home && uri.is_none() {
+        let uri = if cfg.general.new_page != config::NewPage::Blank && uri.is_none() {
             Some(cfg.general.homepage.as_str())
         } else {
             uri
@@ -67,24 +171,65 @@ impl Gui {
             newtab.controls.set_reload_button_sensitive(true);
             newtab.viewer.visit(uri);
         }
+    }
+
+    /// Reopens the most recently closed tab in this window, along with its
+    /// back/forward list
+    fn reopen_closed_tab(&self) {
+        let nav = self.closed_tabs.borrow_mut().pop();
+        if let Some(nav) = nav {
+            let tab = self.add_tab();
+            tab.restore(nav);
+            if let Some(page) = self.notebook.page_num(&tab.tab()) {
+                self.notebook.set_page(page.try_into().unwrap());
+            }
+        }
+    }
+
+    /// Returns the back/forward lists of every tab in this window, in order
+    fn tab_navigation(&self) -> Vec<Navigation> {
+        (0..self.notebook.n_pages())
+            .filter_map(|n| self.nth_tab(n))
+            .map(|tab| tab.navigation())
+            .filter(|nav| nav.current().is_some())
+            .collect()
+    }
+
+    /// Returns the state of this window for saving in the session file
+    fn session_window(&self) -> session::Window {
+        let (width, height) = self.window.default_size();
+        session::Window {
+            width,
+            height,
+            maximized: self.window.is_maximized(),
+            active: self.current_page().unwrap_or(0),
+            tabs: self.tab_navigation(),
+        }
+    }
+
+    /// Creates a new, empty tab and connects its signals
+    fn add_tab(&self) -> Tab {
+        let newtab = tab::Tab::init();
+        self.tabs
+            .borrow_mut()
+            .insert(newtab.tab().widget_name().to_string(), newtab.clone());
         self.notebook
             .append_page(&newtab.tab(), Some(&newtab.label));
         self.notebook.set_tab_reorderable(&newtab.tab(), true);
         newtab.connect_signals();
+        newtab.bookmark_editor.set_private(self.is_private());
         newtab.upload.set_transient_for(Some(&self.window));
+        let closed_tabs = self.closed_tabs.clone();
         newtab.label.close_button().connect_clicked(
             clone!(@strong newtab as tab, @weak self.notebook as nb => move |_| {
-                let _name = tab.tab().widget_name().to_string();
+                remember_closed(&closed_tabs, tab.navigation());
                 nb.detach_tab(&tab.tab());
             }),
         );
         newtab.viewer.connect_page_load_started(
             clone!(@weak self.window as window, @strong newtab as tab => move |_, uri| {
-                window.set_title(Some(&format!(
-                    "{}-{} - [loading]",
-                    env!("CARGO_PKG_NAME"),
-                    env!("CARGO_PKG_VERSION"),
-                )));
+                set_title(&window, "[loading]");
+                tab.set_style(&uri);
                 tab.controls.set_uri(&uri);
                 tab.set_label("[loading]", true);
                 tab.controls.set_reload_button_sensitive(false);
@@ -94,9 +239,29 @@ impl Gui {
             clone!(@strong newtab as tab, @weak self.window as window => move |_, uri| {
                 tab.controls.set_uri(&uri);
                 tab.controls.set_reload_button_sensitive(true);
-                tab.controls.set_back_button_sensitive(tab.viewer.has_previous());
-                tab.controls.set_forward_button_sensitive(tab.viewer.has_next());
+                tab.record_visit(&uri);
                 tab.update_bookmark_editor();
+                if !window.has_css_class("private") {
+                    let mut history = HISTORY.lock().unwrap();
+                    history.append(&uri, tab.page_title().as_deref());
+                    if let Err(e) = history.save() {
+                        eprintln!("{}", e);
+                    }
+                    drop(history);
+                    let mut bookmarks = BOOKMARKS.lock().unwrap();
+                    if bookmarks.visit(&uri) {
+                        if let Err(e) = bookmarks.save() {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    drop(bookmarks);
+                    let mut feeds = FEEDS.lock().unwrap();
+                    if feeds.mark_read(&uri) {
+                        if let Err(e) = feeds.save() {
+                            eprintln!("{}", e);
+                        }
+                    }
+                }
                 if let Ok(url) = Url::parse(uri.as_str()) {
                     let scheme = url.scheme();
                     let host = url.host_str().unwrap_or_else(|| {
@@ -106,12 +271,7 @@ impl Gui {
                             "Unknown host"
                         }
                     });
-                    window.set_title(Some(&format!(
-                        "{}-{} - {}",
-                        env!("CARGO_PKG_NAME"),
-                        env!("CARGO_PKG_VERSION"),
-                        host,
-                    )));
+                    set_title(&window, host);
                     tab.set_label(host, false);
                 }
             }),
@@ -119,23 +279,18 @@ impl Gui {
         newtab.viewer.connect_page_load_failed(
             clone!(@strong newtab as tab, @weak self.window as window => move |_, err| {
                 tab.controls.set_reload_button_sensitive(true);
-                tab.controls.set_back_button_sensitive(tab.viewer.has_previous());
-                tab.controls.set_forward_button_sensitive(tab.viewer.has_next());
+                tab.update_navigation_buttons();
                 if err.contains("unsupported-scheme") {
                     if let Ok(url) = Url::parse(tab.viewer.uri().as_str()) {
                         if let Some(host) = url.host_str() {
                             tab.set_label(host, false);
-                            window.set_title(Some(&format!(
-                                "{}-{} -{}",
-                                env!("CARGO_PKG_NAME"),
-                                env!("CARGO_PKG_VERSION"),
-                                host,
-                            )));
+                            set_title(&window, host);
                         }
                     }
                     tab.controls.set_uri(tab.viewer.uri().as_str());
                     return;
                 }
+                tab.record_visit(tab.viewer.uri().as_str());
                 tab.set_label("Load failure", false);
                 tab.viewer.render_gmi(&format!(
                     "# Page load failure\n\n{}",
@@ -150,11 +305,7 @@ impl Gui {
                         s => s,
                     },
                 ));
-                window.set_title(Some(&format!(
-                    "{}-{} - page load failed",
-                    env!("CARGO_PKG_NAME"),
-                    env!("CARGO_PKG_VERSION"),
-                )));
+                set_title(&window, "page load failed");
             }),
         );
         newtab
@@ -163,8 +314,9 @@ impl Gui {
                 gui.new_tab(Some(&uri));
             }));
         if let Some(app) = self.window.application() {
+            let private = self.is_private();
             newtab.viewer.connect_request_new_window(move |_, uri| {
-                let gui = build_ui(&app);
+                let gui = build_ui(&app, private);
                 gui.new_tab(Some(&uri));
             });
         }
@@ -173,12 +325,7 @@ impl Gui {
                 if let Ok(url) = Url::parse(&url) {
                     if let Some(host) = url.host_str() {
                         tab.set_label(host, false);
-                        window.set_title(Some(&format!(
-                            "{}-{} - {}",
-                            env!("CARGO_PKG_NAME"),
-                            env!("CARGO_PKG_VERSION"),
-                            host,
-                        )));
+                        set_title(&window, host);
                     }
                 }
                 tab.controls.set_uri(&url);
@@ -190,12 +337,7 @@ impl Gui {
                 if let Ok(url) = Url::parse(&url) {
                     if let Some(host) = url.host_str() {
                         tab.set_label(host, false);
-                        window.set_title(Some(&format!(
-                            "{}-{} - {}",
-                            env!("CARGO_PKG_NAME"),
-                            env!("CARGO_PKG_VERSION"),
-                            host,
-                        )));
+                        set_title(&window, host);
                     }
                 }
                 tab.controls.set_uri(&url);
@@ -274,6 +416,65 @@ impl Gui {
         }
     }
 
+    /// Asks the user what to do about each data file which could not be read
+    /// when Eva started
+    fn recover_files(&self) {
+        for failure in storage::take_failures() {
+            self.ask_recover(failure);
+        }
+    }
+
+    /// Offers to restore the file which could not be read from its most
+    /// recent backup, or to start over without it. Either way the damaged
+    /// file is kept. Until the user decides, the file is not written to.
+    fn ask_recover(&self, failure: storage::Failure) {
+        let name = failure
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backups = storage::backups(&failure.path);
+        let question = match backups.first() {
+            Some((_, made)) => format!(
+                "Restore the most recent backup, made {}?",
+                DateTime::<Local>::from(*made).format("%Y-%m-%d %H:%M"),
+            ),
+            None => String::from("There is no backup of it. Start over without it?"),
+        };
+        let dlg = gtk::MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(gtk::MessageType::Warning)
+            .text(&format!("{} could not be read", name))
+            .secondary_text(&format!(
+                "{}\n\n{} The damaged file will be kept as {}.broken. Until you decide, \
+                changes to it are not saved.",
+                failure.error, question, name,
+            ))
+            .build();
+        dlg.add_button("Decide later", ResponseType::Cancel);
+        dlg.add_button("Start over", ResponseType::Reject);
+        if !backups.is_empty() {
+            dlg.add_button("Restore backup", ResponseType::Accept);
+        }
+        dlg.connect_response(clone!(@strong self as gui => move |dlg, res| {
+            let path = &failure.path;
+            let result = match res {
+                ResponseType::Accept => recover_file(path, &backups),
+                ResponseType::Reject => storage::set_aside(path)
+                    .map(|_| format!("Started over without {}", name))
+                    .map_err(Into::into),
+                _ => Ok(format!("{} will not be saved until it is recovered", name)),
+            };
+            match result {
+                Ok(message) => gui.send_notification(&message),
+                Err(e) => gui.send_notification(&format!("Error: {}", e)),
+            }
+            dlg.close();
+        }));
+        dlg.show();
+    }
+
     fn send_notification(&self, message: &str) {
         if let Some(application) = self.window.application() {
             let notification = Notification::new(env!("CARGO_PKG_NAME"));
@@ -335,6 +536,8 @@ impl Gui {
             if let Some(tab) = self.current_tab() {
                 let name = tab.tab().widget_name().to_string();
                 self.tabs.borrow_mut().remove(&name);
+                tab.clear_css();
+                remember_closed(&self.closed_tabs, tab.navigation());
             }
             self.notebook.remove_page(Some(page));
         }
@@ -354,6 +557,7 @@ impl Gui {
             match self.notebook.page_num(&tab.tab()) {
                 Some(_) => {}
                 None => {
+                    tab.clear_css();
                     let _rem = self.tabs.borrow_mut().remove(&name);
                 }
             }
@@ -381,7 +585,7 @@ impl Gui {
 
     fn go_previous(&self) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(tab) = self.current_tab() {
-            tab.viewer.go_previous();
+            tab.go_previous();
             Ok(())
         } else {
             Err(String::from("Error getting tab").into())
@@ -390,7 +594,7 @@ impl Gui {
 
     fn go_next(&self) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(tab) = self.current_tab() {
-            tab.viewer.go_next();
+            tab.go_next();
             Ok(())
         } else {
             Err(String::from("Error getting tab").into())
@@ -401,12 +605,7 @@ impl Gui {
         if let Some(tab) = self.nth_tab(page) {
             let uri = tab.viewer.uri();
             if let Ok(url) = Url::parse(uri.as_str()) {
-                self.window.set_title(Some(&format!(
-                    "{}-{} - {}",
-                    env!("CARGO_PKG_NAME"),
-                    env!("CARGO_PKG_VERSION"),
-                    url.host_str().unwrap_or("Unknown host"),
-                )));
+                set_title(&self.window, url.host_str().unwrap_or("Unknown host"));
             }
         }
     }
@@ -429,39 +628,215 @@ impl Gui {
     }
 
     fn set_css(&self, colors: &config::Colors) {
-        let provider = CssProvider::new();
         let context = self.window.style_context();
-        let css = include_str!("gemview.css")
-            .replace("NORMAL_FG_COLOR", &colors.fg.to_string())
-            .replace("NORMAL_BG_COLOR", &colors.bg.to_string())
-            .replace("QUOTE_FG_COLOR", &colors.quote_fg.to_string())
-            .replace("QUOTE_BG_COLOR", &colors.quote_bg.to_string())
-            .replace("PRE_FG_COLOR", &colors.pre_fg.to_string())
-            .replace("PRE_BG_COLOR", &colors.pre_bg.to_string())
-            .replace("LINK_COLOR", &colors.link.to_string())
-            .replace("HOVER_COLOR", &colors.hover.to_string())
-            .replace("DEFAULT_FG_COLOR", &context.color().to_string())
-            .replace("ReducedRGBA", "rgba")
-            .replace("RGBA", "rgba");
-        provider.load_from_data(css.as_bytes());
-        StyleContext::add_provider_for_display(
-            &Display::default().expect("Cannot connect to display"),
-            &provider,
-            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION,
-        );
+        let css = style_sheet(colors, &context.color().to_string());
+        CSS.with(|provider| provider.load_from_data(css.as_bytes()));
     }
 
     fn open_bookmarks(&self) {
         if let Some(tab) = self.current_tab() {
             tab.open_bookmarks();
-            self.window.set_title(Some(&format!(
-                "{}-{} - bookmarks",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION"),
-            )));
+            set_title(&self.window, "bookmarks");
+        }
+    }
+
+    fn open_history(&self) {
+        if let Some(tab) = self.current_tab() {
+            tab.open_history();
+            set_title(&self.window, "history");
+        }
+    }
+
+    fn open_sessions(&self) {
+        if let Some(tab) = self.current_tab() {
+            tab.open_sessions();
+            set_title(&self.window, "sessions");
+        }
+    }
+
+    fn open_feeds(&self) {
+        if let Some(tab) = self.current_tab() {
+            tab.open_feeds();
+            set_title(&self.window, "feeds");
+        }
+    }
+
+    /// Subscribes to the page in the current tab as a feed
+    fn subscribe(&self) {
+        let (uri, title) = match self.current_tab() {
+            Some(tab) => (tab.viewer.uri(), tab.page_title()),
+            None => return,
+        };
+        if !uri.starts_with("gemini://") {
+            self.send_notification("Only gemini pages can be subscribed to");
+            return;
+        }
+        if FEEDS.lock().unwrap().is_subscribed(&uri) {
+            self.send_notification(&format!("Already subscribed to {uri}"));
+            return;
+        }
+        let gui = self.clone();
+        confirm_write(
+            self.window.upcast_ref(),
+            &format!("Subscribe to {uri}?"),
+            move || {
+                let mut feeds = FEEDS.lock().unwrap();
+                if feeds.subscribe(&uri, title.as_deref()) {
+                    if let Err(e) = feeds.save() {
+                        eprintln!("{}", e);
+                    }
+                    drop(feeds);
+                    gui.update_feeds(Some(uri.to_string()));
+                    gui.send_notification(&format!("Subscribed to {uri}"));
+                }
+            },
+        );
+    }
+
+    /// Saves the tabs of this window under `name`, replacing any session
+    /// which already has that name
+    fn save_named_session(&self, name: &str) {
+        let gui = self.clone();
+        let name = String::from(name);
+        confirm_write(
+            self.window.upcast_ref(),
+            &format!("Save this window as {name}?"),
+            move || {
+                let mut sessions = SESSIONS.lock().unwrap();
+                sessions.insert(&name, gui.session_window());
+                if let Err(e) = sessions.save() {
+                    eprintln!("{}", e);
+                }
+                drop(sessions);
+                refresh_sessions();
+            },
+        );
+    }
+
+    fn clear_history(&self) {
+        let mut history = HISTORY.lock().unwrap();
+        history.clear();
+        if let Err(e) = history.save() {
+            eprintln!("{}", e);
+        }
+        drop(history);
+        refresh_history();
+    }
+
+    /// Re-renders every tab in this window which is displaying the history
+    fn refresh_history_tabs(&self) {
+        for tab in self.tabs.borrow().values() {
+            if tab.viewer.uri().as_str().starts_with("eva://history") {
+                tab.refresh_history();
+            }
         }
     }
 
+    /// Shows a file chooser, calling `f` with the chosen file and sending a
+    /// notification with its result
+    fn choose_file<F>(&self, title: &str, action: gtk::FileChooserAction, name: Option<&str>, f: F)
+    where
+        F: Fn(&Path) -> Result<String, Box<dyn Error>> + 'static,
+    {
+        let dlg = Dialogs::file_chooser(&self.window, title, action);
+        if let Some(name) = name {
+            dlg.set_current_name(name);
+        }
+        dlg.connect_response(clone!(@strong self as gui => move |dlg,response| {
+            if response == ResponseType::Accept {
+                if let Some(path) = dlg.file().and_then(|f| f.path()) {
+                    match f(&path) {
+                        Ok(message) => gui.send_notification(&message),
+                        Err(e) => gui.send_notification(&format!("Error: {}", e)),
+                    }
+                }
+            }
+            dlg.destroy();
+        }));
+        dlg.show();
+    }
+
+    /// Asks for a file and exports the history to it. The format is chosen
+    /// from the file's extension, defaulting to gemtext.
+    fn export_history(&self) {
+        self.choose_file(
+            "Export history",
+            gtk::FileChooserAction::Save,
+            Some("history.gmi"),
+            |path| {
+                let format = history::Format::from_path(path).unwrap_or(history::Format::Gemtext);
+                let text = HISTORY.lock().unwrap().export(format)?;
+                fs::write(path, text)?;
+                Ok(format!("History exported: {}", path.display()))
+            },
+        );
+    }
+
+    /// Asks for a file and merges the history in it into Eva's own. The
+    /// format is chosen from the file's extension, defaulting to gemtext.
+    fn import_history(&self) {
+        let gui = self.clone();
+        confirm_write(self.window.upcast_ref(), "Import history?", move || {
+            gui.choose_file(
+                "Import history",
+                gtk::FileChooserAction::Open,
+                None,
+                |path| {
+                    let format =
+                        history::Format::from_path(path).unwrap_or(history::Format::Gemtext);
+                    let text = fs::read_to_string(path)?;
+                    let mut history = HISTORY.lock().unwrap();
+                    let count = history.import(&text, format)?;
+                    history.save()?;
+                    drop(history);
+                    refresh_history();
+                    Ok(format!("Imported {} history entries", count))
+                },
+            );
+        });
+    }
+
+    /// Asks for a file and exports the bookmarks to it, in a format chosen
+    /// from the file's name
+    fn export_bookmarks(&self) {
+        self.choose_file(
+            "Export bookmarks",
+            gtk::FileChooserAction::Save,
+            Some("bookmarks.html"),
+            |path| {
+                let format = bookmarks::Format::from_path(path)
+                    .ok_or("Unknown bookmarks format, try .gmi, .ini, .xml or .html")?;
+                let text = BOOKMARKS.lock().unwrap().export(format);
+                fs::write(path, text)?;
+                Ok(format!("Bookmarks exported: {}", path.display()))
+            },
+        );
+    }
+
+    /// Asks for a bookmarks file from another browser and adds the bookmarks
+    /// in it to Eva's own
+    fn import_bookmarks(&self) {
+        let gui = self.clone();
+        confirm_write(self.window.upcast_ref(), "Import bookmarks?", move || {
+            gui.choose_file(
+                "Import bookmarks",
+                gtk::FileChooserAction::Open,
+                None,
+                |path| {
+                    let text = fs::read_to_string(path)?;
+                    let format =
+                        bookmarks::Format::detect(path, &text).ok_or("Unknown bookmarks format")?;
+                    let mut bookmarks = BOOKMARKS.lock().unwrap();
+                    let count = bookmarks.import(&text, format);
+                    bookmarks.save()?;
+                    drop(bookmarks);
+                    refresh_bookmarks();
+                    Ok(format!("Imported {} bookmarks", count))
+                },
+            );
+        });
+    }
+
     fn save_page(&self) {
         if let Some(tab) = self.current_tab() {
             let viewer = tab.viewer;
@@ -522,7 +897,7 @@ impl Gui {
 pub fn run() {
     let application = Rc::new(gtk::Application::new(
         Some("org.hitchhiker-linux.eva"),
-        gtk::gio::ApplicationFlags::HANDLES_OPEN,
+        gtk::gio::ApplicationFlags::HANDLES_OPEN | gtk::gio::ApplicationFlags::HANDLES_COMMAND_LINE,
     ));
 
     application.add_main_option(
@@ -543,7 +918,7 @@ pub fn run() {
         None,
     );
 
-    application.connect_handle_local_options(move |_, dict| {
+    application.connect_handle_local_options(|_, dict| {
         if dict.contains("version") {
             println!("{}", env!("CARGO_PKG_VERSION"));
             return 1;
@@ -551,34 +926,186 @@ pub fn run() {
         -1
     });
 
+    application.connect_startup(|app| {
+        glib::timeout_add_seconds_local(AUTOSAVE_INTERVAL, || {
+            save_session();
+            glib::Continue(true)
+        });
+        load_data();
+        watch_files(app);
+        scheme::connect_changed(apply_colors);
+        update_feeds(None, feeds::Trust::Saved);
+        glib::timeout_add_seconds_local(FEED_INTERVAL, || {
+            update_feeds(None, feeds::Trust::Saved);
+            glib::Continue(true)
+        });
+    });
+
     match application.register(Some(&Cancellable::new())) {
         Ok(_) => {}
         Err(e) => eprintln!("{}", e),
     };
 
-    application.connect_open(move |app, addr, _| {
-        let gui = build_ui(app);
-        for uri in addr {
-            gui.new_tab(Some(&uri.uri()));
+    // The command line is handled by the instance which is already running,
+    // if there is one, so that `--private` applies to the window it opens
+    application.connect_command_line(|app, cmdline| {
+        let private = cmdline.options_dict().contains("private");
+        let uris: Vec<String> = cmdline
+            .arguments()
+            .iter()
+            .skip(1)
+            .map(|arg| cmdline.create_file_for_arg(arg).uri().to_string())
+            .collect();
+        if uris.is_empty() {
+            start_window(app, private);
+        } else {
+            open_uris(app, &uris, private);
         }
+        0
     });
-    application.connect_activate(|app| {
-        let gui = build_ui(app);
-        gui.new_tab(None);
+    application.connect_open(|app, addr, _| {
+        let uris: Vec<String> = addr.iter().map(|uri| uri.uri().to_string()).collect();
+        open_uris(app, &uris, false);
+    });
+    application.connect_activate(|app| start_window(app, false));
+    application.connect_shutdown(|_| {
+        if CONFIG.lock().unwrap().history.clear_on_exit {
+            let mut history = HISTORY.lock().unwrap();
+            history.clear();
+            if let Err(e) = history.save() {
+                eprintln!("{}", e);
+            }
+        }
     });
     application.run();
 }
 
-pub fn build_ui(app: &Application) -> Rc<Gui> {
+/// Sets the window title, marking private windows as such
+fn set_title(window: &gtk::ApplicationWindow, subtitle: &str) {
+    window.set_title(Some(&format!(
+        "{}-{} - {}{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        subtitle,
+        if window.has_css_class("private") {
+            " [private]"
+        } else {
+            ""
+        },
+    )));
+}
+
+/// Saves the tabs and geometry of every open window which is not private.
+/// Nothing is written if no such window is open.
+fn save_session() {
+    let windows: Vec<session::Window> = WINDOWS.with(|windows| {
+        windows
+            .borrow()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|gui| !gui.is_private())
+            .map(|gui| gui.session_window())
+            .collect()
+    });
+    if windows.is_empty() {
+        return;
+    }
+    if let Err(e) = (session::Session { windows }).save() {
+        eprintln!("{}", e);
+    }
+}
+
+/// Opens the windows saved in the session file. Returns `false` if there was
+/// no session to restore.
+fn restore_session(app: &Application) -> bool {
+    let session = match session::Session::from_file() {
+        Ok(Some(s)) if !s.is_empty() => s,
+        Ok(_) => return false,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
+    };
+    for win in session.windows {
+        open_window(app, win);
+    }
+    true
+}
+
+/// Opens a new window containing the tabs saved in `win`
+fn open_window(app: &Application, win: session::Window) {
+    if win.tabs.is_empty() {
+        return;
+    }
+    let gui = create_ui(app, false);
+    if win.width > 0 && win.height > 0 {
+        gui.window.set_default_size(win.width, win.height);
+    }
+    if win.maximized {
+        gui.window.maximize();
+    }
+    let last = win.tabs.len() - 1;
+    for nav in win.tabs {
+        let tab = gui.add_tab();
+        tab.restore(nav);
+    }
+    let active = usize::try_from(win.active).unwrap_or(0).min(last);
+    gui.notebook.set_page(active.try_into().unwrap());
+    gui.window.show();
+    gui.recover_files();
+}
+
+/// Opens the named session `name` in a new window
+pub fn open_named_session(app: &Application, name: &str) {
+    let win = SESSIONS.lock().unwrap().get(name).map(|s| s.window.clone());
+    if let Some(win) = win {
+        open_window(app, win);
+    }
+}
+
+pub fn build_ui(app: &Application, private: bool) -> Rc<Gui> {
+    let gui = create_ui(app, private);
+    gui.window.show();
+    gui.recover_files();
+    gui
+}
+
+/// Opens a window with the homepage, or restores the last session if that is
+/// how Eva is set to start
+fn start_window(app: &Application, private: bool) {
+    let restore = !private
+        && app.windows().is_empty()
+        && CONFIG.lock().unwrap().general.new_page == config::NewPage::LastSession;
+    if restore && restore_session(app) {
+        return;
+    }
+    let gui = build_ui(app, private);
+    gui.new_tab(None);
+}
+
+/// Opens a window with a tab for each of `uris`
+fn open_uris(app: &Application, uris: &[String], private: bool) {
+    let gui = build_ui(app, private);
+    for uri in uris {
+        gui.new_tab(Some(uri));
+    }
+}
+
+/// Creates a new window without showing it
+fn create_ui(app: &Application, private: bool) -> Rc<Gui> {
     let gui = Rc::new(Gui::default());
+    WINDOWS.with(|windows| windows.borrow_mut().push(Rc::downgrade(&gui)));
+    if private {
+        gui.set_private();
+    }
     actions::add(&gui, app);
     let config = CONFIG.lock().unwrap().clone();
-    gui.set_css(&config.colors);
+    gui.set_css(&config.colors_for(scheme::prefers_dark()));
     gui.window.set_application(Some(app));
     gui.notebook
-        .connect_page_removed(clone!(@weak gui, @strong config => move |nb,_page,_| {
+        .connect_page_removed(clone!(@weak gui => move |nb,_page,_| {
             gui.cleanup_tabs();
-            let multi = config.general.show_tabs == config::ShowTabs::Multiple;
+            let multi = CONFIG.lock().unwrap().general.show_tabs == config::ShowTabs::Multiple;
             match nb.n_pages() {
                 0 => gui.window.close(),
                 1 => if multi { nb.set_show_tabs(false); },
@@ -586,8 +1113,9 @@ pub fn build_ui(app: &Application) -> Rc<Gui> {
             }
         }));
     gui.notebook
-        .connect_page_added(clone!(@weak gui, @strong config => move |nb,_page,_| {
-            if nb.n_pages() > 1 && config.general.show_tabs == config::ShowTabs::Multiple {
+        .connect_page_added(clone!(@weak gui => move |nb,_page,_| {
+            let multi = CONFIG.lock().unwrap().general.show_tabs == config::ShowTabs::Multiple;
+            if nb.n_pages() > 1 && multi {
                 nb.set_show_tabs(true);
             }
         }));
@@ -599,15 +1127,14 @@ pub fn build_ui(app: &Application) -> Rc<Gui> {
         .preferences
         .connect_response(clone!(@weak gui => move |dlg,res| {
             if res == ResponseType::Accept {
-                if let Some(cfg) = gui.dialogs.preferences.config() {
-                    *CONFIG.lock().unwrap() = cfg.clone();
+                if let Some(mut cfg) = gui.dialogs.preferences.config() {
                     if let Err(e) = cfg.save_to_file(&config::get_config_file()) {
                         eprintln!("{}", e);
                     }
-                    gui.set_general(&cfg.general);
-                    gui.set_css(&cfg.colors);
-                    for (_,tab) in gui.tabs.borrow().clone() {
-                        tab.set_fonts();
+                    *CONFIG.lock().unwrap() = cfg;
+                    apply_config();
+                    if let Err(e) = HISTORY.lock().unwrap().save() {
+                        eprintln!("{}", e);
                     }
                 } else {
                     gui.dialogs.preferences.load_config();
@@ -615,8 +1142,439 @@ pub fn build_ui(app: &Application) -> Rc<Gui> {
             }
             dlg.hide();
         }));
+    gui.window.connect_close_request(
+        clone!(@weak gui => @default-return gtk::Inhibit(false), move |_| {
+            // Saving both before and after forgetting this window keeps the
+            // last window to be closed in the session, as the second save is
+            // skipped when no other windows remain
+            save_session();
+            WINDOWS.with(|windows| {
+                windows
+                    .borrow_mut()
+                    .retain(|w| w.upgrade().map_or(false, |w| !Rc::ptr_eq(&w, &gui)));
+            });
+            save_session();
+            for tab in gui.tabs.borrow().values() {
+                tab.clear_css();
+            }
+            if !gui.is_private() {
+                let tabs = gui.tab_navigation();
+                if !tabs.is_empty() {
+                    let mut closed = CLOSED_WINDOWS.lock().unwrap();
+                    closed.push(tabs);
+                    if closed.len() > MAX_CLOSED {
+                        closed.remove(0);
+                    }
+                }
+            }
+            gtk::Inhibit(false)
+        }),
+    );
     gui.set_general(&config.general);
-
-    gui.window.show();
     gui
 }
+
+/// Updates the bookmark icons and bookmark pages in the tabs of every window
+pub fn refresh_bookmarks() {
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_bookmarks();
+        }
+    }
+}
+
+/// Reads the user's data files up front, so that any which cannot be read are
+/// found before the first window opens
+fn load_data() {
+    lazy_static::initialize(&CONFIG);
+    lazy_static::initialize(&BOOKMARKS);
+    lazy_static::initialize(&HISTORY);
+    lazy_static::initialize(&SESSIONS);
+    lazy_static::initialize(&FEEDS);
+    lazy_static::initialize(&SEARCH);
+}
+
+/// Sets aside the file at `path` which could not be read, then restores the
+/// newest of its `backups` which can be read, loading it in place of the
+/// defaults which Eva started with
+fn recover_file(
+    path: &Path,
+    backups: &[(PathBuf, std::time::SystemTime)],
+) -> Result<String, Box<dyn Error>> {
+    let name = path.display();
+    let _broken = storage::set_aside(path)?;
+    for (backup, _) in backups {
+        storage::restore(path, backup)?;
+        match reload_file(path) {
+            Ok(()) => return Ok(format!("Restored {} from {}", name, backup.display())),
+            Err(e) => eprintln!("Unable to restore {}: {}", backup.display(), e),
+        }
+    }
+    fs::remove_file(path)?;
+    Err(format!("None of the backups of {} could be read", name).into())
+}
+
+/// Loads the data file at `path` again, replacing what is held in memory
+fn reload_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    if path == bookmarks::get_bookmarks_file() {
+        let loaded = bookmarks::Bookmarks::from_file()?.unwrap_or_default();
+        *BOOKMARKS.lock().unwrap() = loaded;
+        refresh_bookmarks();
+    } else if path == config::get_config_file() {
+        let loaded = config::Config::from_file()?.unwrap_or_default();
+        *CONFIG.lock().unwrap() = loaded;
+        apply_config();
+    } else if path == feeds::get_feeds_file() {
+        let loaded = feeds::Feeds::from_file()?.unwrap_or_default();
+        *FEEDS.lock().unwrap() = loaded;
+        refresh_feeds();
+    } else if path == session::get_named_sessions_file() {
+        let loaded = session::NamedSessions::from_file()?.unwrap_or_default();
+        *SESSIONS.lock().unwrap() = loaded;
+    } else if path == uri::get_search_file() {
+        let loaded = uri::Search::from_file()?.unwrap_or_default();
+        *SEARCH.lock().unwrap() = loaded;
+    } else if path == history::get_history_log() || path == history::get_history_file() {
+        let retention = CONFIG.lock().unwrap().history.clone();
+        let loaded = history::History::from_file(&retention)?
+            .unwrap_or_else(|| history::History::new(&retention));
+        *HISTORY.lock().unwrap() = loaded;
+        refresh_history();
+    }
+    Ok(())
+}
+
+/// Re-renders the history page in the tabs of every window
+fn refresh_history() {
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        gui.refresh_history_tabs();
+    }
+}
+
+/// Applies the config to every window after it has changed: the colors,
+/// fonts, capsule styles and tab settings, and the preferences dialog unless
+/// it is being edited
+fn apply_config() {
+    let cfg = CONFIG.lock().unwrap().clone();
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    let colors = cfg.colors_for(scheme::prefers_dark());
+    HISTORY.lock().unwrap().set_retention(&cfg.history);
+    for gui in windows {
+        gui.set_general(&cfg.general);
+        gui.set_css(&colors);
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_style();
+        }
+        if !gui.dialogs.preferences.is_visible() {
+            gui.dialogs.preferences.load_config();
+        }
+    }
+    refresh_bookmarks();
+}
+
+/// Switches every window to the colors of the light or dark theme after the
+/// desktop's preference has changed
+fn apply_colors() {
+    let colors = CONFIG.lock().unwrap().colors_for(scheme::prefers_dark());
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        gui.set_css(&colors);
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_style();
+        }
+    }
+}
+
+/// Fills in the colors of the page style sheet
+fn style_sheet(colors: &config::Colors, default_fg: &str) -> String {
+    include_str!("gemview.css")
+        .replace("NORMAL_FG_COLOR", &colors.fg.to_string())
+        .replace("NORMAL_BG_COLOR", &colors.bg.to_string())
+        .replace("QUOTE_FG_COLOR", &colors.quote_fg.to_string())
+        .replace("QUOTE_BG_COLOR", &colors.quote_bg.to_string())
+        .replace("PRE_FG_COLOR", &colors.pre_fg.to_string())
+        .replace("PRE_BG_COLOR", &colors.pre_bg.to_string())
+        .replace("LINK_COLOR", &colors.link.to_string())
+        .replace("HOVER_COLOR", &colors.hover.to_string())
+        .replace("DEFAULT_FG_COLOR", default_fg)
+        .replace("ReducedRGBA", "rgba")
+        .replace("RGBA", "rgba")
+}
+
+/// Limits the page rules of `css` to widgets inside one with the css class
+/// `class`, leaving out the rules for the rest of the window
+fn scope_style_sheet(css: &str, class: &str) -> String {
+    css.split_inclusive('}')
+        .filter_map(|rule| {
+            let (selector, body) = rule.split_once('{')?;
+            let selector = selector.trim();
+            if selector.contains(".gemview") {
+                Some(format!(".{class} {selector} {{{body}\n"))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Sends a desktop notification which does not belong to any one window
+fn notify(app: &Application, message: &str) {
+    let notification = Notification::new(env!("CARGO_PKG_NAME"));
+    notification.set_body(Some(message));
+    app.send_notification(None, &notification);
+}
+
+/// Calls `reload` whenever the file at `path` has been written
+fn watch_file<F: Fn() + 'static>(path: PathBuf, reload: F) {
+    let file = gio::File::for_path(path);
+    match file.monitor_file(gio::FileMonitorFlags::NONE, Cancellable::NONE) {
+        Ok(monitor) => {
+            monitor.connect_changed(move |_, _, _, event| {
+                if matches!(
+                    event,
+                    gio::FileMonitorEvent::ChangesDoneHint
+                        | gio::FileMonitorEvent::Created
+                        | gio::FileMonitorEvent::MovedIn
+                        | gio::FileMonitorEvent::Renamed
+                ) {
+                    reload();
+                }
+            });
+            MONITORS.with(|monitors| monitors.borrow_mut().push(monitor));
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Calls `reload` when a file in the directory at `path` is added, removed or
+/// changed
+fn watch_dir<F: Fn() + 'static>(path: PathBuf, reload: F) {
+    let dir = gio::File::for_path(path);
+    match dir.monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, Cancellable::NONE) {
+        Ok(monitor) => {
+            monitor.connect_changed(move |_, _, _, event| {
+                if matches!(
+                    event,
+                    gio::FileMonitorEvent::ChangesDoneHint
+                        | gio::FileMonitorEvent::Deleted
+                        | gio::FileMonitorEvent::MovedIn
+                        | gio::FileMonitorEvent::MovedOut
+                        | gio::FileMonitorEvent::Renamed
+                ) {
+                    reload();
+                }
+            });
+            MONITORS.with(|monitors| monitors.borrow_mut().push(monitor));
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// Wraps `reload`, which reads the config file `name` again, so that if the
+/// file cannot be read the user is told once rather than each time it is
+/// written. The running configuration is kept until the file is fixed.
+fn reload_config_file<F>(app: &Application, name: &'static str, reload: F) -> impl Fn()
+where
+    F: Fn() -> Result<(), Box<dyn Error>> + 'static,
+{
+    let app = app.clone();
+    let reported = RefCell::new(None);
+    move || match reload() {
+        Ok(()) => *reported.borrow_mut() = None,
+        Err(e) => {
+            let message = format!("{} was not reloaded: {}", name, e);
+            eprintln!("{}", message);
+            if reported.borrow().as_ref() != Some(&message) {
+                notify(&app, &message);
+                *reported.borrow_mut() = Some(message);
+            }
+        }
+    }
+}
+
+/// Watches the user's data and config files for changes made by another
+/// instance of Eva or by hand, merging them in and updating every window.
+/// Changes made here are seen as well, but the merge finds nothing new in
+/// them.
+fn watch_files(app: &Application) {
+    watch_file(bookmarks::get_bookmarks_file(), || {
+        let reloaded = BOOKMARKS.lock().unwrap().reload();
+        match reloaded {
+            Ok(true) => refresh_bookmarks(),
+            Ok(false) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    });
+    watch_file(history::get_history_log(), || {
+        let synced = HISTORY.lock().unwrap().sync();
+        match synced {
+            Ok(true) => refresh_history(),
+            Ok(false) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+    });
+    watch_file(
+        config::get_config_file(),
+        reload_config_file(app, "config.toml", || {
+            if CONFIG.lock().unwrap().reload()? {
+                apply_config();
+            }
+            Ok(())
+        }),
+    );
+    let application = app.clone();
+    watch_file(
+        keys::get_key_file(),
+        reload_config_file(app, "keys.toml", move || {
+            let keys = keys::Keys::from_file()?.unwrap_or_default();
+            actions::set_accels(&application, &keys);
+            Ok(())
+        }),
+    );
+    let themes = config::get_theme_dir();
+    if let Err(e) = fs::create_dir_all(&themes) {
+        eprintln!("{}", e);
+    }
+    watch_dir(themes, || {
+        config::Theme::reload();
+        apply_config();
+    });
+    watch_file(
+        uri::get_search_file(),
+        reload_config_file(app, "search.toml", || {
+            let search = uri::Search::from_file()?.unwrap_or_default();
+            *SEARCH.lock().unwrap() = search;
+            Ok(())
+        }),
+    );
+}
+
+/// Fetches the subscribed feeds, or only the feed at `only`, on another
+/// thread, then updates any tabs displaying the feeds. Servers are checked
+/// against the known hosts in `trust`.
+fn update_feeds(only: Option<String>, trust: feeds::Trust) {
+    let all = only.is_none();
+    if all && UPDATING_FEEDS.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    receiver.attach(None, move |()| {
+        if all {
+            UPDATING_FEEDS.store(false, Ordering::SeqCst);
+        }
+        refresh_feeds();
+        glib::Continue(false)
+    });
+    std::thread::spawn(move || {
+        feeds::Feeds::update(&FEEDS, only.as_deref(), &trust);
+        if let Err(e) = sender.send(()) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+/// Checks every bookmark for a broken link on other threads, updating any
+/// tabs displaying the bookmarks as the results come in. Servers are checked
+/// against the known hosts in `trust`.
+fn check_bookmarks(trust: feeds::Trust) {
+    if CHECKING_BOOKMARKS.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    receiver.attach(None, move |finished| {
+        if finished {
+            CHECKING_BOOKMARKS.store(false, Ordering::SeqCst);
+            refresh_bookmarks();
+        } else {
+            refresh_link_report();
+        }
+        glib::Continue(!finished)
+    });
+    std::thread::spawn(move || {
+        let check = |url: &str| bookmarks::check(url, &trust);
+        bookmarks::Bookmarks::check_links(&BOOKMARKS, check, || {
+            if let Err(e) = sender.send(false) {
+                eprintln!("{}", e);
+            }
+        });
+        if let Err(e) = sender.send(true) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+/// Re-renders the link check report in the tabs of every window, to show how
+/// far the check has got
+fn refresh_link_report() {
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_link_report();
+        }
+    }
+}
+
+/// Re-renders the feed pages in the tabs of every window
+fn refresh_feeds() {
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_feeds();
+        }
+    }
+}
+
+/// Re-renders `eva://sessions` in the tabs of every window
+fn refresh_sessions() {
+    let windows: Vec<Rc<Gui>> =
+        WINDOWS.with(|windows| windows.borrow().iter().filter_map(Weak::upgrade).collect());
+    for gui in windows {
+        for tab in gui.tabs.borrow().values() {
+            tab.refresh_sessions();
+        }
+    }
+}
+
+/// Runs `f`, which writes to disk, straight away, or in a private window
+/// only once the user has agreed to it
+fn confirm_write<F: Fn() + 'static>(window: &gtk::Window, text: &str, f: F) {
+    if !window.has_css_class("private") {
+        f();
+        return;
+    }
+    let dlg = gtk::MessageDialog::builder()
+        .transient_for(window)
+        .modal(true)
+        .message_type(gtk::MessageType::Question)
+        .buttons(gtk::ButtonsType::YesNo)
+        .text(text)
+        .secondary_text("This is a private window. This change will be written to disk.")
+        .build();
+    dlg.connect_response(move |dlg, res| {
+        if res == ResponseType::Yes {
+            f();
+        }
+        dlg.close();
+    });
+    dlg.show();
+}
+
+/// Reopens the most recently closed window, restoring its tabs
+pub fn reopen_closed_window(app: &Application) {
+    let tabs = CLOSED_WINDOWS.lock().unwrap().pop();
+    if let Some(tabs) = tabs {
+        let gui = build_ui(app, false);
+        for nav in tabs {
+            let tab = gui.add_tab();
+            tab.restore(nav);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Finds out whether the desktop prefers a light or a dark color scheme, and
+//! notices when that changes. GNOME and desktops following it publish the
+//! preference as the `color-scheme` setting, and otherwise the Gtk theme is
+//! checked for being a dark one.
+use {
+    gtk::{gio, prelude::*},
+    std::rc::Rc,
+};
+
+const INTERFACE_SCHEMA: &str = "org.gnome.desktop.interface";
+const COLOR_SCHEME_KEY: &str = "color-scheme";
+
+thread_local! {
+    /// The desktop settings holding `color-scheme`, if they are installed,
+    /// kept here so that change notifications keep coming
+    static INTERFACE: Option<gio::Settings> = interface_settings();
+}
+
+/// Opens the desktop interface settings, only if they have a `color-scheme`
+/// key, as opening settings which are not installed aborts
+fn interface_settings() -> Option<gio::Settings> {
+    let schema = gio::SettingsSchemaSource::default()?.lookup(INTERFACE_SCHEMA, true)?;
+    if schema.has_key(COLOR_SCHEME_KEY) {
+        Some(gio::Settings::new(INTERFACE_SCHEMA))
+    } else {
+        None
+    }
+}
+
+/// Whether the desktop prefers a dark color scheme
+pub fn prefers_dark() -> bool {
+    let scheme = INTERFACE.with(|settings| {
+        settings
+            .as_ref()
+            .map(|settings| settings.string(COLOR_SCHEME_KEY).to_string())
+    });
+    match scheme.as_deref() {
+        Some("prefer-dark") => true,
+        Some("prefer-light") => false,
+        _ => gtk::Settings::default().map_or(false, |settings| {
+            settings.is_gtk_application_prefer_dark_theme()
+                || settings
+                    .gtk_theme_name()
+                    .map_or(false, |name| name.to_lowercase().contains("dark"))
+        }),
+    }
+}
+
+/// Calls `f` whenever the desktop's light or dark preference may have changed
+pub fn connect_changed<F: Fn() + 'static>(f: F) {
+    let f = Rc::new(f);
+    INTERFACE.with(|settings| {
+        if let Some(settings) = settings {
+            let f = f.clone();
+            settings.connect_changed(Some(COLOR_SCHEME_KEY), move |_, _| f());
+        }
+    });
+    if let Some(settings) = gtk::Settings::default() {
+        let g = f.clone();
+        settings.connect_gtk_application_prefer_dark_theme_notify(move |_| g());
+        settings.connect_gtk_theme_name_notify(move |_| f());
+    }
+}
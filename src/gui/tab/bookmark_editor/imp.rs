@@ -1,8 +1,11 @@
-use gtk::{
-    glib::{self, subclass::InitializingObject},
-    prelude::*,
-    subclass::prelude::*,
-    CompositeTemplate,
+use {
+    gtk::{
+        glib::{self, subclass::InitializingObject},
+        prelude::*,
+        subclass::prelude::*,
+        CompositeTemplate,
+    },
+    std::cell::{Cell, RefCell},
 };
 
 #[derive(CompositeTemplate, Default)]
@@ -19,9 +22,18 @@ pub struct BookmarkEditor {
     #[template_child]
     pub tags: TemplateChild<gtk::Entry>,
     #[template_child]
+    pub folder: TemplateChild<gtk::ComboBoxText>,
+    #[template_child]
+    pub keyword: TemplateChild<gtk::Entry>,
+    #[template_child]
     pub cancel: TemplateChild<gtk::Button>,
     #[template_child]
+    pub delete: TemplateChild<gtk::Button>,
+    #[template_child]
     pub accept: TemplateChild<gtk::Button>,
+    pub private: Cell<bool>,
+    /// The url of the bookmark being edited, if it already exists
+    pub editing: RefCell<Option<String>>,
 }
 
 #[glib::object_subclass]
@@ -3,7 +3,7 @@
 mod imp;
 
 use {
-    crate::BOOKMARKS,
+    crate::{bookmarks::Bookmark, BOOKMARKS},
     gtk::{
         glib::{self, GString, Object},
         prelude::*,
@@ -31,17 +31,105 @@ impl BookmarkEditor {
         editor.imp().cancel.connect_clicked(move |_| ed.popdown());
         let ed = editor.clone();
         editor.imp().accept.connect_clicked(move |_| {
-            let bm = (&ed).into();
-            let mut bmarks = BOOKMARKS.lock().unwrap();
-            bmarks.update(&bm);
-            if let Err(e) = bmarks.save() {
-                eprintln!("Error: {}", e);
+            let bm = Bookmark::from(&ed);
+            let editing = ed.imp().editing.borrow().clone();
+            if ed.imp().private.get() {
+                ed.confirm_save(editing, bm);
+            } else {
+                ed.save(editing.as_deref(), &bm);
+            }
+            ed.popdown();
+        });
+        let ed = editor.clone();
+        editor.imp().delete.connect_clicked(move |_| {
+            if let Some(url) = ed.imp().editing.borrow().clone() {
+                ed.confirm_delete(url);
             }
             ed.popdown();
         });
         editor
     }
 
+    /// Sets whether this editor belongs to a private window, in which case
+    /// the user is asked before the bookmarks file is written
+    pub fn set_private(&self, private: bool) {
+        self.imp().private.set(private);
+    }
+
+    /// Saves `bm`, replacing the bookmark which was being edited in case its
+    /// url was changed
+    fn save(&self, editing: Option<&str>, bm: &Bookmark) {
+        let mut bmarks = BOOKMARKS.lock().unwrap();
+        match editing {
+            Some(old_url) => bmarks.replace(old_url, bm),
+            None => bmarks.update(bm),
+        }
+        if let Err(e) = bmarks.save() {
+            eprintln!("Error: {}", e);
+        }
+        drop(bmarks);
+        self.bookmarks_changed();
+    }
+
+    fn delete(&self, url: &str) {
+        let mut bmarks = BOOKMARKS.lock().unwrap();
+        if bmarks.remove(url).is_some() {
+            if let Err(e) = bmarks.save() {
+                eprintln!("Error: {}", e);
+            }
+        }
+        drop(bmarks);
+        self.bookmarks_changed();
+    }
+
+    /// Lets the window know that the bookmarks have changed, so that it can
+    /// update the bookmark icons and pages in its tabs
+    fn bookmarks_changed(&self) {
+        if let Err(e) = self.activate_action("win.refresh_bookmarks", None) {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Asks a yes or no question in a dialog, running `f` if the answer is yes
+    fn confirm<F: Fn(&Self) + 'static>(&self, text: &str, secondary_text: &str, f: F) {
+        let dlg = gtk::MessageDialog::builder()
+            .modal(true)
+            .message_type(gtk::MessageType::Question)
+            .buttons(gtk::ButtonsType::YesNo)
+            .text(text)
+            .secondary_text(secondary_text)
+            .build();
+        if let Some(root) = self.root() {
+            if let Ok(window) = root.downcast::<gtk::Window>() {
+                dlg.set_transient_for(Some(&window));
+            }
+        }
+        let ed = self.clone();
+        dlg.connect_response(move |dlg, res| {
+            if res == gtk::ResponseType::Yes {
+                f(&ed);
+            }
+            dlg.close();
+        });
+        dlg.show();
+    }
+
+    fn confirm_save(&self, editing: Option<String>, bm: Bookmark) {
+        self.confirm(
+            "Save bookmark?",
+            "This is a private window. Saving this bookmark will write it to disk.",
+            move |ed| ed.save(editing.as_deref(), &bm),
+        );
+    }
+
+    fn confirm_delete(&self, url: String) {
+        self.confirm(
+            "Delete bookmark?",
+            &format!("\"{}\" will be removed from your bookmarks.", self.name()),
+            move |ed| ed.delete(&url),
+        );
+    }
+
     /// Retrieves the value from the `name` field from the editor
     pub fn name(&self) -> GString {
         self.imp().name.text()
@@ -62,23 +150,55 @@ impl BookmarkEditor {
         self.imp().tags.text()
     }
 
+    /// Retrieves the value from the `keyword` field from the editor
+    pub fn keyword(&self) -> GString {
+        self.imp().keyword.text()
+    }
+
+    /// Retrieves the folder chosen or typed into the `folder` field of the
+    /// editor, which is empty for the top level
+    pub fn folder(&self) -> GString {
+        self.imp().folder.active_text().unwrap_or_default()
+    }
+
+    /// Fills the `folder` field with the existing folders and selects `folder`
+    fn set_folder(&self, folders: &[String], folder: Option<&str>) {
+        let combo = &self.imp().folder;
+        combo.remove_all();
+        for f in folders {
+            combo.append(Some(f), f);
+        }
+        if let Some(entry) = combo.child().and_then(|c| c.downcast::<gtk::Entry>().ok()) {
+            entry.set_text(folder.unwrap_or(""));
+        }
+    }
+
     /// Updates the editor based on whether the current url is bookmarked or not.
     pub fn update(&self, url: &str) -> bool {
         let bmarks = BOOKMARKS.lock().unwrap();
         let matches = bmarks.all.get(url);
+        let folders = bmarks.folders();
         match matches {
             Some(b) => {
                 self.imp().label.set_label("<b>Edit Bookmark</b>");
+                self.imp().editing.replace(Some(b.url()));
+                self.imp().delete.set_visible(true);
                 self.imp().name.set_text(&b.name());
                 self.imp()
                     .description
                     .set_text(&b.description().unwrap_or_default());
                 self.imp().url.set_text(&b.url());
                 self.imp().tags.set_text(&b.tags().join(" "));
+                self.set_folder(&folders, b.folder().as_deref());
+                self.imp()
+                    .keyword
+                    .set_text(&b.keyword().unwrap_or_default());
                 true
             }
             None => {
                 self.imp().label.set_label("<b>Create Bookmark</b>");
+                self.imp().editing.replace(None);
+                self.imp().delete.set_visible(false);
                 if let Ok(u) = Url::parse(url) {
                     self.imp()
                         .name
@@ -87,6 +207,8 @@ impl BookmarkEditor {
                 self.imp().description.set_text("");
                 self.imp().url.set_text(url);
                 self.imp().tags.set_text("");
+                self.set_folder(&folders, None);
+                self.imp().keyword.set_text("");
                 false
             }
         }
@@ -2,16 +2,22 @@ pub mod bookmark_editor;
 pub mod controls;
 pub mod input;
 pub mod label;
-pub use {bookmark_editor::BookmarkEditor, controls::Controls, input::Input, label::Label};
+pub mod navigation;
+pub use {
+    bookmark_editor::BookmarkEditor, controls::Controls, input::Input, label::Label,
+    navigation::Navigation,
+};
 
 use {
-    super::uri,
-    crate::{BOOKMARKS, CONFIG},
+    super::{scheme, uri},
+    crate::{bookmarks, config, history, BOOKMARKS, CONFIG, FEEDS, HISTORY, SESSIONS},
     gemview::GemView,
-    gtk::{glib::clone, prelude::*},
+    gtk::{gdk::Display, glib::clone, prelude::*, CssProvider, StyleContext},
     std::{
+        cell::RefCell,
         fs::File,
         io::{BufReader, Read},
+        rc::Rc,
     },
     url::Url,
 };
@@ -25,6 +31,10 @@ pub struct Tab {
     input: Input,
     pub controls: Controls,
     pub viewer: GemView,
+    nav: Rc<RefCell<Navigation>>,
+    /// The style sheet holding the colors of the capsule being shown, if it
+    /// has its own
+    css: Rc<RefCell<Option<CssProvider>>>,
 }
 
 impl Default for Tab {
@@ -75,6 +85,8 @@ impl Default for Tab {
             bookmark_editor,
             controls,
             viewer,
+            nav: Rc::new(RefCell::new(Navigation::default())),
+            css: Rc::new(RefCell::new(None)),
         }
     }
 }
@@ -82,7 +94,7 @@ impl Default for Tab {
 impl Tab {
     pub fn init() -> Self {
         let tab = Self::default();
-        tab.set_fonts();
+        tab.refresh_style();
         tab.update_bookmark_editor();
         tab.controls.set_back_button_sensitive(false);
         tab.controls.set_forward_button_sensitive(false);
@@ -99,6 +111,7 @@ impl Tab {
             }));
         self.viewer
             .connect_page_load_redirect(clone!(@strong self as tab => move |_, uri| {
+                tab.set_style(&uri);
                 tab.controls.set_uri(&uri);
             }));
         self.viewer.connect_request_unsupported_scheme(
@@ -115,6 +128,12 @@ impl Tab {
                 }
             }),
         );
+        // The editor may have been opened for a bookmark other than the
+        // current page, from the edit links on eva://bookmarks
+        self.bookmark_editor
+            .connect_closed(clone!(@strong self as tab => move |_| {
+                tab.update_bookmark_editor();
+            }));
         let upload = self.upload.clone();
         self.viewer.connect_request_upload(move |_viewer, _url| {
             upload.show();
@@ -163,18 +182,77 @@ impl Tab {
         self.tab.clone()
     }
 
-    pub fn set_fonts(&self) {
-        let cfg = CONFIG.lock().unwrap().clone();
-        self.viewer.set_font_paragraph(cfg.fonts.pg.to_pango());
-        self.viewer.set_font_quote(cfg.fonts.quote.to_pango());
-        self.viewer.set_font_pre(cfg.fonts.pre.to_pango());
-        self.viewer.set_font_h1(cfg.fonts.h1.to_pango());
-        self.viewer.set_font_h2(cfg.fonts.h2.to_pango());
-        self.viewer.set_font_h3(cfg.fonts.h3.to_pango());
+    pub fn set_fonts(&self, fonts: &config::Fonts) {
+        self.viewer.set_font_paragraph(fonts.pg.to_pango());
+        self.viewer.set_font_quote(fonts.quote.to_pango());
+        self.viewer.set_font_pre(fonts.pre.to_pango());
+        self.viewer.set_font_h1(fonts.h1.to_pango());
+        self.viewer.set_font_h2(fonts.h2.to_pango());
+        self.viewer.set_font_h3(fonts.h3.to_pango());
+    }
+
+    /// Switches to the colors and fonts for the capsule serving `uri`, or
+    /// back to the configured ones if it has no style of its own
+    pub fn set_style(&self, uri: &str) {
+        let host = Url::parse(uri)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        let cfg = CONFIG.lock().unwrap();
+        let fonts = cfg.capsule_fonts(host.as_deref());
+        let colors = host
+            .as_deref()
+            .and_then(|host| cfg.capsule_colors(host, scheme::prefers_dark()));
+        drop(cfg);
+        self.set_fonts(&fonts);
+        match colors {
+            Some(colors) => self.set_css(&colors),
+            None => self.clear_css(),
+        }
+    }
+
+    /// Applies the style for the page being shown again, after the config
+    /// has changed
+    pub fn refresh_style(&self) {
+        self.set_style(&self.viewer.uri());
+    }
+
+    /// Shows this tab's pages in `colors` rather than the window's colors
+    fn set_css(&self, colors: &config::Colors) {
+        let class = format!("tab-{}", self.tab.widget_name());
+        self.tab.add_css_class(&class);
+        let default_fg = self.tab.style_context().color().to_string();
+        let css = super::scope_style_sheet(&super::style_sheet(colors, &default_fg), &class);
+        let mut current = self.css.borrow_mut();
+        let provider = current.get_or_insert_with(|| {
+            let provider = CssProvider::new();
+            if let Some(display) = Display::default() {
+                StyleContext::add_provider_for_display(
+                    &display,
+                    &provider,
+                    gtk::STYLE_PROVIDER_PRIORITY_APPLICATION + 1,
+                );
+            }
+            provider
+        });
+        provider.load_from_data(css.as_bytes());
+    }
+
+    /// Returns this tab to the window's colors
+    pub fn clear_css(&self) {
+        if let Some(provider) = self.css.borrow_mut().take() {
+            if let Some(display) = Display::default() {
+                StyleContext::remove_provider_for_display(&display, &provider);
+            }
+        }
     }
 
     pub fn update_bookmark_editor(&self) {
-        if self.bookmark_editor.update(self.viewer.uri().as_str()) {
+        let bookmarked = self.bookmark_editor.update(self.viewer.uri().as_str());
+        self.set_bookmark_icon(bookmarked);
+    }
+
+    fn set_bookmark_icon(&self, bookmarked: bool) {
+        if bookmarked {
             self.controls
                 .set_bookmark_icon_name("user-bookmarks-symbolic");
         } else {
@@ -187,24 +265,61 @@ impl Tab {
         self.label.set(label, spin);
     }
 
+    /// Adds `uri` to this tab's back/forward list
+    pub fn record_visit(&self, uri: &str) {
+        self.nav.borrow_mut().visit(uri);
+        self.update_navigation_buttons();
+    }
+
+    pub fn update_navigation_buttons(&self) {
+        let nav = self.nav.borrow();
+        self.controls.set_back_button_sensitive(nav.has_previous());
+        self.controls.set_forward_button_sensitive(nav.has_next());
+    }
+
+    pub fn go_previous(&self) {
+        let uri = self.nav.borrow_mut().go_previous();
+        if let Some(uri) = uri {
+            self.update_navigation_buttons();
+            self.viewer.visit(&uri);
+        }
+    }
+
+    pub fn go_next(&self) {
+        let uri = self.nav.borrow_mut().go_next();
+        if let Some(uri) = uri {
+            self.update_navigation_buttons();
+            self.viewer.visit(&uri);
+        }
+    }
+
+    /// Returns a copy of this tab's back/forward list
+    pub fn navigation(&self) -> Navigation {
+        self.nav.borrow().clone()
+    }
+
+    /// Replaces this tab's back/forward list and displays its current page
+    pub fn restore(&self, nav: Navigation) {
+        let current = nav.current();
+        *self.nav.borrow_mut() = nav;
+        self.update_navigation_buttons();
+        if let Some(uri) = current {
+            if let Ok(u) = Url::parse(&uri) {
+                self.set_label(u.host_str().unwrap_or("Unknown host"), false);
+            }
+            self.controls.set_uri(&uri);
+            self.controls.set_reload_button_sensitive(true);
+            self.viewer.visit(&uri);
+        }
+    }
+
     pub fn request_eva_page(&self, uri: &str) {
         if let Ok(url) = Url::parse(uri) {
             match url.host_str() {
-                Some("bookmarks") => match url.path() {
-                    "" | "/" => self.open_bookmarks(),
-                    "/tags" | "/tags/" => self.open_bookmark_tags(),
-                    p => {
-                        let maybe_tag = p.replace("/tags/", "");
-                        let bookmarks = BOOKMARKS.lock().unwrap();
-                        if let Some(page) = bookmarks.tag_to_gmi(&maybe_tag) {
-                            self.viewer.render_gmi(&page);
-                            self.viewer.set_uri(uri);
-                            self.controls.set_uri("uri");
-                            self.set_label("bookmarks", false);
-                        }
-                    }
-                },
-                //Some("history") => {}
+                Some("bookmarks") => self.request_bookmarks_page(&url),
+                Some("history") => self.request_history_page(&url),
+                Some("sessions") => self.request_sessions_page(&url),
+                Some("feeds") => self.request_feeds_page(&url),
                 Some("source") => {
                     self.view_source();
                 }
@@ -213,26 +328,541 @@ impl Tab {
         }
     }
 
-    pub fn open_bookmarks(&self) {
-        let bookmarks = BOOKMARKS.lock().unwrap();
-        let page = bookmarks.to_gmi();
-        self.viewer.render_gmi(&page);
-        self.viewer.set_uri("eva://bookmarks");
-        self.controls.set_uri("eva://bookmarks");
+    /// Displays one of Eva's internal pages
+    fn render_eva_page(&self, uri: &str, page: &str, label: &str) {
+        self.set_style(uri);
+        self.viewer.render_gmi(page);
+        self.viewer.set_uri(uri);
+        self.controls.set_uri(uri);
         self.controls
             .set_bookmark_icon_name("bookmark-new-symbolic");
-        self.set_label("bookmarks", false);
+        self.set_label(label, false);
+        self.record_visit(uri);
+    }
+
+    pub fn open_bookmarks(&self) {
+        let view = CONFIG.lock().unwrap().bookmarks.clone();
+        let page = BOOKMARKS.lock().unwrap().to_gmi(&view);
+        self.render_eva_page("eva://bookmarks", &page, "bookmarks");
+    }
+
+    fn request_bookmarks_page(&self, url: &Url) {
+        let query = url
+            .query()
+            .and_then(|q| urlencoding::decode(q).ok())
+            .map(std::borrow::Cow::into_owned);
+        match url.path() {
+            "" | "/" => match &query {
+                Some(query) => {
+                    let view = CONFIG.lock().unwrap().bookmarks.clone();
+                    let page = BOOKMARKS.lock().unwrap().search_to_gmi(query, &view);
+                    self.render_eva_page(url.as_str(), &page, "bookmarks");
+                }
+                None => self.open_bookmarks(),
+            },
+            "/search" => {
+                self.request_input("Search bookmarks", String::from("eva://bookmarks"), true);
+            }
+            "/tags" | "/tags/" => self.open_bookmark_tags(),
+            "/sort" => {
+                if let Some(sort) = query.as_deref().and_then(config::BookmarkSort::from_name) {
+                    self.set_bookmark_view(move |view| view.sort = sort);
+                }
+            }
+            "/group" => {
+                self.set_bookmark_view(|view| view.group_by_tag = !view.group_by_tag);
+            }
+            "/edit" => {
+                if let Some(bookmark) = &query {
+                    self.bookmark_editor.update(bookmark);
+                    self.bookmark_editor.popup();
+                }
+            }
+            "/delete" => {
+                if let Some(bookmark) = query {
+                    self.confirm_write("Delete bookmark?", move |tab| {
+                        let mut bookmarks = BOOKMARKS.lock().unwrap();
+                        if bookmarks.remove(&bookmark).is_some() {
+                            if let Err(e) = bookmarks.save() {
+                                eprintln!("{}", e);
+                            }
+                        }
+                        drop(bookmarks);
+                        tab.bookmarks_changed();
+                        tab.open_bookmarks();
+                    });
+                }
+            }
+            "/export" => {
+                if let Err(e) = self.tab.activate_action("win.export_bookmarks", None) {
+                    eprintln!("{}", e);
+                }
+            }
+            "/import" => {
+                if let Err(e) = self.tab.activate_action("win.import_bookmarks", None) {
+                    eprintln!("{}", e);
+                }
+            }
+            "/undo" => self.confirm_write("Restore bookmark?", |tab| {
+                let mut bookmarks = BOOKMARKS.lock().unwrap();
+                if bookmarks.undo_remove() {
+                    if let Err(e) = bookmarks.save() {
+                        eprintln!("{}", e);
+                    }
+                }
+                drop(bookmarks);
+                tab.bookmarks_changed();
+                tab.open_bookmarks();
+            }),
+            "/check" => self.confirm_write("Check bookmarks for broken links?", |tab| {
+                tab.activate_window_action("win.check_bookmarks", None);
+                tab.open_link_report();
+            }),
+            "/check/report" => self.open_link_report(),
+            "/check/update" => self.confirm_write("Update bookmarks?", move |tab| {
+                let mut bookmarks = BOOKMARKS.lock().unwrap();
+                let changed = match &query {
+                    Some(bookmark) => bookmarks.follow_redirect(bookmark),
+                    None => bookmarks.follow_redirects() > 0,
+                };
+                if changed {
+                    if let Err(e) = bookmarks.save() {
+                        eprintln!("{}", e);
+                    }
+                }
+                drop(bookmarks);
+                tab.bookmarks_changed();
+                tab.open_link_report();
+            }),
+            "/check/delete" => {
+                if let Some(bookmark) = query {
+                    self.confirm_write("Delete bookmark?", move |tab| {
+                        let mut bookmarks = BOOKMARKS.lock().unwrap();
+                        if bookmarks.remove(&bookmark).is_some() {
+                            if let Err(e) = bookmarks.save() {
+                                eprintln!("{}", e);
+                            }
+                        }
+                        drop(bookmarks);
+                        tab.bookmarks_changed();
+                        tab.open_link_report();
+                    });
+                }
+            }
+            p if p == "/folder" || p.starts_with("/folder/") => self.open_bookmark_folder(url),
+            p if p.starts_with("/tags/") => self.request_bookmark_tag_page(url),
+            _ => {}
+        }
+    }
+
+    /// Handles `eva://bookmarks/tags/<tag>`, which lists the bookmarks with
+    /// that tag, and the pages below it which rename, merge or delete the tag
+    fn request_bookmark_tag_page(&self, url: &Url) {
+        let segments: Vec<String> = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .skip(1)
+                    .filter_map(|s| urlencoding::decode(s).ok())
+                    .map(std::borrow::Cow::into_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let query = url
+            .query()
+            .and_then(|q| urlencoding::decode(q).ok())
+            .map(std::borrow::Cow::into_owned);
+        let (tag, action) = match &segments[..] {
+            [tag] => (tag, None),
+            [tag, action] => (tag, Some(action.as_str())),
+            _ => return self.open_bookmark_tags(),
+        };
+        let tag_url = format!("eva://bookmarks/tags/{}", urlencoding::encode(tag));
+        match (action, query) {
+            (None, _) => {
+                let view = CONFIG.lock().unwrap().bookmarks.clone();
+                let page = BOOKMARKS.lock().unwrap().tag_to_gmi(tag, &view);
+                match page {
+                    Some(page) => self.render_eva_page(url.as_str(), &page, "bookmarks"),
+                    None => self.open_bookmark_tags(),
+                }
+            }
+            (Some("rename"), None) => self.request_input(
+                &format!("Rename the tag {tag} to"),
+                format!("{tag_url}/rename"),
+                true,
+            ),
+            (Some("merge"), None) => self.request_input(
+                &format!("Merge the tag {tag} into"),
+                format!("{tag_url}/merge"),
+                true,
+            ),
+            (Some("rename" | "merge"), Some(new)) => {
+                let tag = tag.clone();
+                self.confirm_write("Change tag?", move |tab| {
+                    let mut bookmarks = BOOKMARKS.lock().unwrap();
+                    if bookmarks.rename_tag(&tag, &new) > 0 {
+                        if let Err(e) = bookmarks.save() {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    drop(bookmarks);
+                    tab.bookmarks_changed();
+                    tab.open_bookmark_tags();
+                });
+            }
+            (Some("delete"), _) => {
+                let tag = tag.clone();
+                self.confirm_write("Delete tag?", move |tab| {
+                    let mut bookmarks = BOOKMARKS.lock().unwrap();
+                    if bookmarks.delete_tag(&tag) > 0 {
+                        if let Err(e) = bookmarks.save() {
+                            eprintln!("{}", e);
+                        }
+                    }
+                    drop(bookmarks);
+                    tab.bookmarks_changed();
+                    tab.open_bookmark_tags();
+                });
+            }
+            _ => self.open_bookmark_tags(),
+        }
+    }
+
+    /// Changes how the bookmark pages are laid out and saves the choice
+    fn set_bookmark_view<F: Fn(&mut config::BookmarkView) + 'static>(&self, f: F) {
+        self.confirm_write("Change the bookmark layout?", move |tab| {
+            let mut cfg = CONFIG.lock().unwrap();
+            f(&mut cfg.bookmarks);
+            if let Err(e) = cfg.save_to_file(&config::get_config_file()) {
+                eprintln!("{}", e);
+            }
+            drop(cfg);
+            tab.bookmarks_changed();
+            tab.open_bookmarks();
+        });
+    }
+
+    /// Runs `f`, which writes to disk, straight away, or in a private window
+    /// only once the user has agreed to it
+    fn confirm_write<F: Fn(&Self) + 'static>(&self, text: &str, f: F) {
+        match self
+            .tab
+            .root()
+            .and_then(|root| root.downcast::<gtk::Window>().ok())
+        {
+            Some(window) => {
+                let tab = self.clone();
+                super::confirm_write(&window, text, move || f(&tab));
+            }
+            None => f(self),
+        }
+    }
+
+    /// Passes a request to one of the window's actions, which know whether
+    /// the window is private
+    fn activate_window_action(&self, name: &str, param: Option<&str>) {
+        let param = param.map(ToVariant::to_variant);
+        if let Err(e) = self.tab.activate_action(name, param.as_ref()) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn bookmarks_changed(&self) {
+        if let Err(e) = self.tab.activate_action("win.refresh_bookmarks", None) {
+            eprintln!("{}", e);
+        }
+    }
+
+    /// Updates the bookmark icon, or the page itself when displaying one of
+    /// the bookmark pages, after the bookmarks have changed
+    pub fn refresh_bookmarks(&self) {
+        let uri = self.viewer.uri();
+        if uri == "eva://bookmarks"
+            || uri.starts_with("eva://bookmarks?")
+            || uri.starts_with("eva://bookmarks/tags")
+            || uri.starts_with("eva://bookmarks/folder")
+            || uri == "eva://bookmarks/check/report"
+        {
+            self.request_eva_page(&uri);
+        } else if self.bookmark_editor.is_visible() {
+            // Leave whatever is being typed into the editor alone
+            let bookmarked = BOOKMARKS.lock().unwrap().all.contains_key(uri.as_str());
+            self.set_bookmark_icon(bookmarked);
+        } else {
+            self.update_bookmark_editor();
+        }
+    }
+
+    pub fn refresh_link_report(&self) {
+        if self.viewer.uri() == "eva://bookmarks/check/report" {
+            self.open_link_report();
+        }
+    }
+
+    /// Displays the bookmarks in the folder named by the path of `url`, such as
+    /// `eva://bookmarks/folder/gemini/tech`
+    fn open_bookmark_folder(&self, url: &Url) {
+        let folder = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .skip(1)
+                    .filter_map(|s| urlencoding::decode(s).ok())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .and_then(|f| bookmarks::normalize_folder(&f));
+        match folder {
+            Some(folder) => {
+                let view = CONFIG.lock().unwrap().bookmarks.clone();
+                let page = BOOKMARKS.lock().unwrap().folder_to_gmi(&folder, &view);
+                self.render_eva_page(url.as_str(), &page, "bookmarks");
+            }
+            None => self.open_bookmarks(),
+        }
+    }
+
+    fn open_link_report(&self) {
+        let page = BOOKMARKS.lock().unwrap().check_report_to_gmi();
+        self.render_eva_page("eva://bookmarks/check/report", &page, "bookmarks");
     }
 
     fn open_bookmark_tags(&self) {
-        let bookmarks = BOOKMARKS.lock().unwrap();
-        let page = bookmarks.tags_to_gmi();
-        self.viewer.render_gmi(&page);
-        self.viewer.set_uri("eva://bookmarks/tags");
-        self.controls.set_uri("eva://bookmarks/tags");
-        self.controls
-            .set_bookmark_icon_name("bookmark-new-symbolic");
-        self.set_label("bookmarks", false);
+        let page = BOOKMARKS.lock().unwrap().tags_to_gmi();
+        self.render_eva_page("eva://bookmarks/tags", &page, "bookmarks");
+    }
+
+    pub fn open_history(&self) {
+        self.render_history("eva://history", None, 1);
+    }
+
+    fn render_history(&self, uri: &str, query: Option<&str>, page: usize) {
+        let page = HISTORY.lock().unwrap().page(query, page);
+        self.render_eva_page(uri, &page, "history");
+    }
+
+    fn request_history_page(&self, url: &Url) {
+        let query = url
+            .query()
+            .and_then(|q| urlencoding::decode(q).ok())
+            .map(std::borrow::Cow::into_owned);
+        match url.path() {
+            "/search" => {
+                self.request_input("Search history", String::from("eva://history"), true);
+            }
+            // Choosing a file needs a dialog belonging to the window
+            "/export" => {
+                if let Err(e) = self.tab.activate_action("win.export_history", None) {
+                    eprintln!("{}", e);
+                }
+            }
+            "/import" => {
+                if let Err(e) = self.tab.activate_action("win.import_history", None) {
+                    eprintln!("{}", e);
+                }
+            }
+            "/forget" | "/forget_host" => self.forget_history(url),
+            p => {
+                let page = p
+                    .strip_prefix("/page/")
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .unwrap_or(1);
+                self.render_history(url.as_str(), query.as_deref(), page);
+            }
+        }
+    }
+
+    /// Removes the page or host given in `url` from the history, then shows
+    /// the page of the history which the link was followed from again
+    fn forget_history(&self, url: &Url) {
+        let mut forget = None;
+        let mut query = None;
+        let mut page = 1;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "url" | "host" => forget = Some(value.into_owned()),
+                "query" => query = Some(value.into_owned()),
+                "page" => page = value.parse().unwrap_or(1),
+                _ => {}
+            }
+        }
+        if let Some(forget) = &forget {
+            let mut history = HISTORY.lock().unwrap();
+            if url.path() == "/forget_host" {
+                history.remove_host(forget);
+            } else {
+                history.remove(forget);
+            }
+            if let Err(e) = history.save() {
+                eprintln!("{}", e);
+            }
+        }
+        let uri = history::page_url(query.as_deref(), page);
+        self.render_history(&uri, query.as_deref(), page);
+    }
+
+    /// Re-renders the page of the history displayed in this tab, keeping its
+    /// query and page number
+    pub fn refresh_history(&self) {
+        if let Ok(url) = Url::parse(self.viewer.uri().as_str()) {
+            if matches!(url.path(), "" | "/") || url.path().starts_with("/page/") {
+                self.request_history_page(&url);
+            }
+        }
+    }
+
+    pub fn open_sessions(&self) {
+        let page = SESSIONS.lock().unwrap().to_gmi();
+        self.render_eva_page("eva://sessions", &page, "sessions");
+    }
+
+    /// Asks for a name under which to save the tabs of this tab's window
+    pub fn request_session_name(&self) {
+        self.request_input(
+            "Save this window as",
+            String::from("eva://sessions/save"),
+            true,
+        );
+    }
+
+    /// Saving and opening sessions concern the whole window, so those requests
+    /// are passed on to the window's actions
+    fn request_sessions_page(&self, url: &Url) {
+        let name = url
+            .query()
+            .and_then(|q| urlencoding::decode(q).ok())
+            .map(std::borrow::Cow::into_owned);
+        match (url.path(), name) {
+            ("/save", None) => self.request_session_name(),
+            ("/save", Some(name)) => {
+                if let Err(e) = self
+                    .tab
+                    .activate_action("win.save_named_session", Some(&name.to_variant()))
+                {
+                    eprintln!("{}", e);
+                }
+                self.open_sessions();
+            }
+            ("/open", Some(name)) => {
+                if let Err(e) = self
+                    .tab
+                    .activate_action("win.open_named_session", Some(&name.to_variant()))
+                {
+                    eprintln!("{}", e);
+                }
+            }
+            ("/delete", Some(name)) => {
+                self.confirm_write(&format!("Delete the session {name}?"), move |tab| {
+                    let mut sessions = SESSIONS.lock().unwrap();
+                    sessions.remove(&name);
+                    if let Err(e) = sessions.save() {
+                        eprintln!("{}", e);
+                    }
+                    drop(sessions);
+                    tab.open_sessions();
+                });
+            }
+            _ => self.open_sessions(),
+        }
+    }
+
+    /// Re-renders `eva://sessions` after the sessions have changed
+    pub fn refresh_sessions(&self) {
+        if self.viewer.uri() == "eva://sessions" {
+            self.open_sessions();
+        }
+    }
+
+    pub fn open_feeds(&self) {
+        let page = FEEDS.lock().unwrap().to_gmi();
+        self.render_eva_page("eva://feeds", &page, "feeds");
+    }
+
+    fn open_subscriptions(&self) {
+        let page = FEEDS.lock().unwrap().subscriptions_to_gmi();
+        self.render_eva_page("eva://feeds/subscriptions", &page, "feeds");
+    }
+
+    /// Re-renders the feed pages after the feeds have been fetched
+    pub fn refresh_feeds(&self) {
+        match self.viewer.uri().as_str() {
+            "eva://feeds" => self.open_feeds(),
+            "eva://feeds/subscriptions" => self.open_subscriptions(),
+            _ => {}
+        }
+    }
+
+    fn request_feeds_page(&self, url: &Url) {
+        let query = url
+            .query()
+            .and_then(|q| urlencoding::decode(q).ok())
+            .map(std::borrow::Cow::into_owned);
+        match (url.path(), query) {
+            ("/subscriptions", _) => self.open_subscriptions(),
+            ("/refresh", _) => self.confirm_write("Refresh feeds?", |tab| {
+                tab.activate_window_action("win.update_feeds", Some(""));
+                tab.open_feeds();
+            }),
+            ("/subscribe", None) => self.request_input(
+                "Subscribe to the feed at",
+                String::from("eva://feeds/subscribe"),
+                true,
+            ),
+            ("/subscribe", Some(feed)) => {
+                let feed = if feed.contains("://") {
+                    feed
+                } else {
+                    format!("gemini://{feed}")
+                };
+                self.confirm_write(&format!("Subscribe to {feed}?"), move |tab| {
+                    let mut feeds = FEEDS.lock().unwrap();
+                    if feeds.subscribe(&feed, None) {
+                        if let Err(e) = feeds.save() {
+                            eprintln!("{}", e);
+                        }
+                        drop(feeds);
+                        tab.activate_window_action("win.update_feeds", Some(feed.as_str()));
+                    }
+                    tab.open_subscriptions();
+                });
+            }
+            ("/unsubscribe", Some(feed)) => {
+                self.confirm_write(&format!("Unsubscribe from {feed}?"), move |tab| {
+                    let mut feeds = FEEDS.lock().unwrap();
+                    feeds.unsubscribe(&feed);
+                    if let Err(e) = feeds.save() {
+                        eprintln!("{}", e);
+                    }
+                    drop(feeds);
+                    tab.open_subscriptions();
+                });
+            }
+            ("/read", _) => self.confirm_write("Mark every entry as read?", |tab| {
+                let mut feeds = FEEDS.lock().unwrap();
+                feeds.mark_all_read();
+                if let Err(e) = feeds.save() {
+                    eprintln!("{}", e);
+                }
+                drop(feeds);
+                tab.open_feeds();
+            }),
+            _ => self.open_feeds(),
+        }
+    }
+
+    /// Returns the first heading of the current page, if it is gemtext
+    pub fn page_title(&self) -> Option<String> {
+        if self.viewer.buffer_mime() != "text/gemini" {
+            return None;
+        }
+        let content = self.viewer.buffer_content();
+        let content = String::from_utf8_lossy(&content);
+        content.lines().find_map(|line| {
+            line.strip_prefix('#')
+                .map(|h| h.trim_start_matches('#').trim().to_string())
+                .filter(|h| !h.is_empty())
+        })
     }
 
     pub fn view_source(&self) {
@@ -0,0 +1,58 @@
+//! The back/forward list of a tab. This is kept separately from the list in
+//! `GemView` so that it can be saved when a tab is closed and restored later.
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Navigation {
+    previous: Vec<String>,
+    current: Option<String>,
+    next: Vec<String>,
+}
+
+impl Navigation {
+    /// Records that `uri` has been displayed. Visiting a new page clears the
+    /// forward list, while displaying the current page again does nothing.
+    pub fn visit(&mut self, uri: &str) {
+        if self.current.as_deref() == Some(uri) {
+            return;
+        }
+        if let Some(current) = self.current.take() {
+            self.previous.push(current);
+        }
+        self.next.clear();
+        self.current = Some(String::from(uri));
+    }
+
+    /// Moves back one step, returning the uri which should be displayed
+    pub fn go_previous(&mut self) -> Option<String> {
+        let uri = self.previous.pop()?;
+        if let Some(current) = self.current.replace(uri.clone()) {
+            self.next.push(current);
+        }
+        Some(uri)
+    }
+
+    /// Moves forward one step, returning the uri which should be displayed
+    pub fn go_next(&mut self) -> Option<String> {
+        let uri = self.next.pop()?;
+        if let Some(current) = self.current.replace(uri.clone()) {
+            self.previous.push(current);
+        }
+        Some(uri)
+    }
+
+    #[must_use]
+    pub fn has_previous(&self) -> bool {
+        !self.previous.is_empty()
+    }
+
+    #[must_use]
+    pub fn has_next(&self) -> bool {
+        !self.next.is_empty()
+    }
+
+    #[must_use]
+    pub fn current(&self) -> Option<String> {
+        self.current.clone()
+    }
+}
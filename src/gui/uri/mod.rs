@@ -1,9 +1,16 @@
 use {
-    crate::{config::get_config_dir, BOOKMARKS, SEARCH},
+    crate::{bookmarks::Bookmarks, config::get_config_dir, storage, BOOKMARKS, SEARCH},
     serde::{Deserialize, Serialize},
-    std::{collections::HashMap, fs, path::PathBuf},
+    std::{collections::HashMap, error::Error, fs, path::PathBuf},
 };
 
+/// Returns the path to search.toml
+pub fn get_search_file() -> PathBuf {
+    let mut file = get_config_dir();
+    file.push("search.toml");
+    file
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Search {
     pub default: String,
@@ -37,8 +44,15 @@ impl Default for Search {
 }
 
 impl Search {
+    /// Puts `query` in place of `%s` in the url of the search engine, or
+    /// appends it if there is no `%s`, the same as for keyword bookmarks
     fn query(se: &str, query: &[&str]) -> String {
-        format!("{}?{}", se, query.join("%20"))
+        let query = query.join("%20");
+        if se.contains("%s") {
+            se.replace("%s", &query)
+        } else {
+            format!("{}?{}", se, query)
+        }
     }
 
     fn build(&self, query: &str) -> String {
@@ -54,48 +68,136 @@ impl Search {
         }
     }
 
-    fn save(&self) {
-        let mut file = get_config_dir();
-        file.push(PathBuf::from("search.toml"));
-        let toml_string = toml::to_string(&self).expect("Could not encode TOML value");
-        fs::write(file, toml_string).expect("Could not write to file!");
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let toml_string = toml::to_string(&self)?;
+        storage::write(&get_search_file(), toml_string)?;
+        Ok(())
+    }
+
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        let file = get_search_file();
+        if !file.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&file)?;
+        Ok(Some(toml::from_str(&contents)?))
     }
 
+    /// Loads the search engines, writing out the defaults if there is no
+    /// search.toml yet
     pub fn load() -> Self {
-        let mut file = get_config_dir();
-        file.push(PathBuf::from("search.toml"));
-        if let Ok(contents) = fs::read_to_string(&file) {
-            if let Ok(search) = toml::from_str(&contents) {
-                return search;
+        match Self::from_file() {
+            Ok(Some(search)) => search,
+            Ok(None) => {
+                let search = Search::default();
+                if let Err(e) = search.save() {
+                    eprintln!("{}", e);
+                }
+                search
+            }
+            Err(e) => {
+                storage::load_failed(&get_search_file(), &e);
+                Search::default()
             }
         }
-        let search = Search::default();
-        if !file.exists() {
-            search.save();
-        }
-        search
     }
 }
 
 pub fn uri(uri: &mut str) -> String {
-    if uri.contains(':') {
-        (*uri).to_string()
+    let search = SEARCH.lock().unwrap().clone();
+    let bookmarks = BOOKMARKS.lock().unwrap();
+    resolve(uri, &bookmarks, &search)
+}
+
+/// Turns text typed into the address bar into the url to open, which may be
+/// a keyword bookmark, the name of a bookmark, a local file, a hostname or
+/// failing those a search
+fn resolve(uri: &str, bookmarks: &Bookmarks, search: &Search) -> String {
+    if let Some(url) = bookmarks.expand_keyword(uri) {
+        url
+    } else if uri.contains(':') {
+        uri.to_string()
     } else if uri.starts_with('/') {
         format!("file://{}", uri)
-    } else if let Some(url) = BOOKMARKS.lock().unwrap().url_from_name(uri) {
+    } else if let Some(url) = bookmarks.url_from_name(uri) {
         url
     } else if let Ok(mut path) = std::env::current_dir() {
         path = path.join(&PathBuf::from(&uri));
         if path.exists() {
             format!("file://{}", path.to_string_lossy())
         } else if uri.contains(' ') || !uri.contains('.') {
-            let search = SEARCH.clone();
             search.build(uri)
         } else {
             format!("gemini://{}", &uri)
         }
     } else {
-        let search = SEARCH.clone();
         search.build(uri)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::bookmarks::BookmarkBuilder};
+
+    fn bookmarks() -> Bookmarks {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.update(
+            &BookmarkBuilder::new()
+                .name("Eva home")
+                .url("gemini://eva.example.org/")
+                .build(),
+        );
+        bookmarks.update(
+            &BookmarkBuilder::new()
+                .name("Wiki")
+                .url("gemini://wiki.example.org/search?%s")
+                .keyword(Some("wp"))
+                .build(),
+        );
+        bookmarks
+    }
+
+    fn resolve(uri: &str) -> String {
+        super::resolve(uri, &bookmarks(), &Search::default())
+    }
+
+    #[test]
+    fn plain_hostname() {
+        assert_eq!(
+            resolve("eva-test.example.org"),
+            "gemini://eva-test.example.org"
+        );
+    }
+
+    #[test]
+    fn full_url() {
+        assert_eq!(
+            resolve("gemini://example.org/a b"),
+            "gemini://example.org/a b"
+        );
+        assert_eq!(resolve("/eva-test/file.gmi"), "file:///eva-test/file.gmi");
+    }
+
+    #[test]
+    fn keyword() {
+        assert_eq!(
+            resolve("wp rust lang"),
+            "gemini://wiki.example.org/search?rust%20lang"
+        );
+        assert_eq!(resolve("wp"), "gemini://wiki.example.org/search");
+    }
+
+    #[test]
+    fn bookmark_name() {
+        assert_eq!(resolve("Eva home"), "gemini://eva.example.org/");
+    }
+
+    #[test]
+    fn search() {
+        assert_eq!(resolve("gus rust"), "gemini://geminispace.info/search?rust");
+        assert_eq!(
+            resolve("eva browser"),
+            "gemini://geminispace.info/search?eva%20browser"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Converting the history to and from the formats used by other programs
+use {
+    super::Entry,
+    chrono::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{error::Error, fmt::Write, path::Path},
+};
+
+/// The date format used for the headings of exported gemtext
+const GMI_DATE: &str = "%Y-%m-%d %H:%M";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A gemtext page with one link per entry
+    Gemtext,
+    Json,
+    /// Comma separated values, with a header row
+    Csv,
+    /// The visited.txt and visited.2.txt files written by Lagrange. These can
+    /// be imported, but not exported.
+    Lagrange,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gmi" | "gemini" => Some(Self::Gemtext),
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            "txt" => Some(Self::Lagrange),
+            _ => None,
+        }
+    }
+}
+
+/// An entry as it appears in exported json. Everything but the url is
+/// optional when importing.
+#[derive(Deserialize, Serialize)]
+struct Exported {
+    url: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    visits: Option<u32>,
+    #[serde(default)]
+    first_visit: Option<DateTime<Local>>,
+    #[serde(default)]
+    last_visit: Option<DateTime<Local>>,
+}
+
+impl Exported {
+    fn into_entry(self) -> (String, Entry) {
+        let last_visit = self
+            .last_visit
+            .or(self.first_visit)
+            .unwrap_or_else(Local::now);
+        (
+            self.url,
+            Entry {
+                title: self.title.filter(|t| !t.is_empty()),
+                visits: self.visits.unwrap_or(1).max(1),
+                first_visit: self.first_visit.unwrap_or(last_visit),
+                last_visit,
+            },
+        )
+    }
+}
+
+/// Quotes a csv field if it contains anything which would break the row
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// Splits csv text into rows of fields, handling quoted fields which may
+/// contain commas, escaped quotes and line breaks
+fn csv_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                let _quote = chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(time.trim())
+        .ok()
+        .map(|t| t.with_timezone(&Local))
+}
+
+/// Writes `entries`, which are expected to be sorted newest first
+pub(super) fn export(
+    entries: &[(&String, &Entry)],
+    format: Format,
+) -> Result<String, Box<dyn Error>> {
+    let mut out = String::new();
+    match format {
+        Format::Gemtext => {
+            out.push_str("# History\n");
+            for (url, entry) in entries {
+                let _ = write!(
+                    out,
+                    "\n### {}\n=> {url} {}\n",
+                    entry.last_visit.format(GMI_DATE),
+                    entry.title.as_deref().unwrap_or(url),
+                );
+            }
+        }
+        Format::Json => {
+            let entries: Vec<Exported> = entries
+                .iter()
+                .map(|(url, entry)| Exported {
+                    url: String::from(*url),
+                    title: entry.title.clone(),
+                    visits: Some(entry.visits),
+                    first_visit: Some(entry.first_visit),
+                    last_visit: Some(entry.last_visit),
+                })
+                .collect();
+            out = serde_json::to_string_pretty(&entries)?;
+        }
+        Format::Csv => {
+            out.push_str("url,title,visits,first_visit,last_visit\n");
+            for (url, entry) in entries {
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv_field(url),
+                    csv_field(entry.title.as_deref().unwrap_or("")),
+                    entry.visits,
+                    entry.first_visit.to_rfc3339(),
+                    entry.last_visit.to_rfc3339(),
+                );
+            }
+        }
+        Format::Lagrange => return Err(String::from("Cannot export to Lagrange format").into()),
+    }
+    Ok(out)
+}
+
+/// Reads the entries from `text`, skipping any which can not be understood
+pub(super) fn import(text: &str, format: Format) -> Result<Vec<(String, Entry)>, Box<dyn Error>> {
+    let entries: Vec<Exported> = match format {
+        Format::Gemtext => {
+            // Links take their date from the heading above them, if it is one
+            // written by Eva's own export
+            let mut date = None;
+            text.lines()
+                .filter_map(|line| {
+                    if let Some(heading) = line.strip_prefix('#') {
+                        let heading = heading.trim_start_matches('#').trim();
+                        date = NaiveDateTime::parse_from_str(heading, GMI_DATE)
+                            .ok()
+                            .and_then(|d| Local.from_local_datetime(&d).single());
+                        return None;
+                    }
+                    let link = line.strip_prefix("=>")?.trim();
+                    let (url, title) = match link.split_once(char::is_whitespace) {
+                        Some((url, title)) => (url, Some(title.trim())),
+                        None => (link, None),
+                    };
+                    Some(Exported {
+                        url: String::from(url),
+                        title: title.filter(|t| *t != url).map(String::from),
+                        visits: None,
+                        first_visit: None,
+                        last_visit: date,
+                    })
+                })
+                .collect()
+        }
+        Format::Json => serde_json::from_str(text)?,
+        Format::Csv => csv_rows(text)
+            .into_iter()
+            .filter(|row| row.first().map_or(false, |url| url.contains(':')))
+            .map(|row| Exported {
+                url: row[0].clone(),
+                title: row.get(1).cloned(),
+                visits: row.get(2).and_then(|v| v.trim().parse().ok()),
+                first_visit: row.get(3).and_then(|t| parse_time(t)),
+                last_visit: row.get(4).and_then(|t| parse_time(t)),
+            })
+            .collect(),
+        // Each line is a unix timestamp, followed in visited.2.txt by a set of
+        // flags in hexadecimal, and then the url
+        Format::Lagrange => text
+            .lines()
+            .filter_map(|line| {
+                let (time, rest) = line.trim().split_once(' ')?;
+                let time = Local.timestamp_opt(time.parse().ok()?, 0).single()?;
+                let url = match rest.split_once(' ') {
+                    Some((flags, url)) if u32::from_str_radix(flags, 16).is_ok() => url,
+                    _ => rest,
+                };
+                Some(Exported {
+                    url: String::from(url.trim()),
+                    title: None,
+                    visits: None,
+                    first_visit: None,
+                    last_visit: Some(time),
+                })
+            })
+            .collect(),
+    };
+    Ok(entries.into_iter().map(Exported::into_entry).collect())
+}
@@ -1,10 +1,47 @@
 #![warn(clippy::all, clippy::pedantic)]
 use {
-    chrono::prelude::*,
-    serde::{Deserialize, Serialize},
-    std::{collections::HashMap, error::Error, fmt::Write, path::PathBuf},
+    crate::{config, storage},
+    chrono::{prelude::*, Duration},
+    record::Record,
+    serde::{Deserialize, Deserializer, Serialize},
+    std::{
+        cmp::Reverse,
+        collections::HashMap,
+        error::Error,
+        fmt::Write,
+        fs::{File, OpenOptions},
+        io::{BufRead, BufReader, Read, Seek, SeekFrom, Write as _},
+        path::{Path, PathBuf},
+    },
+    url::Url,
 };
 
+mod format;
+mod record;
+
+pub use format::Format;
+
+/// The number of entries displayed on each page of `eva://history`
+const PAGE_SIZE: usize = 50;
+
+/// How many more lines than there are entries the log may grow to before it
+/// is compacted
+const COMPACT_SLACK: usize = 1000;
+
+/// Returns the url of `page` of `eva://history`, only showing the urls
+/// containing `query` if it is given
+#[must_use]
+pub fn page_url(query: Option<&str>, page: usize) -> String {
+    let mut url = String::from("eva://history");
+    if page > 1 {
+        let _ = write!(url, "/page/{page}");
+    }
+    if let Some(q) = query {
+        let _ = write!(url, "?{}", urlencoding::encode(q));
+    }
+    url
+}
+
 #[must_use]
 pub fn get_data_dir() -> PathBuf {
     let mut datadir = gtk::glib::user_data_dir();
@@ -13,6 +50,8 @@ pub fn get_data_dir() -> PathBuf {
     datadir
 }
 
+/// Returns the path to history.toml, where older versions of Eva stored the
+/// history
 #[must_use]
 pub fn get_history_file() -> PathBuf {
     let mut histfile = get_data_dir();
@@ -20,63 +59,759 @@ pub fn get_history_file() -> PathBuf {
     histfile
 }
 
+#[must_use]
+pub fn get_history_log() -> PathBuf {
+    let mut histlog = get_data_dir();
+    histlog.push("history.log");
+    histlog
+}
+
+/// Returns the file the history is read from, which is the log unless there
+/// is only a history.toml from an older version of Eva
+#[must_use]
+pub fn get_history_source() -> PathBuf {
+    let histlog = get_history_log();
+    if histlog.exists() {
+        histlog
+    } else {
+        get_history_file()
+    }
+}
+
+/// A single page in the browsing history
 #[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    title: Option<String>,
+    visits: u32,
+    first_visit: DateTime<Local>,
+    last_visit: DateTime<Local>,
+}
+
+impl Entry {
+    fn new(title: Option<&str>) -> Self {
+        let now = Local::now();
+        Self {
+            title: title.map(String::from),
+            visits: 1,
+            first_visit: now,
+            last_visit: now,
+        }
+    }
+
+    #[must_use]
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    #[must_use]
+    pub fn visits(&self) -> u32 {
+        self.visits
+    }
+
+    #[must_use]
+    pub fn first_visit(&self) -> DateTime<Local> {
+        self.first_visit
+    }
+
+    #[must_use]
+    pub fn last_visit(&self) -> DateTime<Local> {
+        self.last_visit
+    }
+
+    /// Combines the number of visits with how recently the page was last
+    /// visited, so that pages which are visited often and recently rank
+    /// highest
+    #[must_use]
+    pub fn frecency(&self) -> u64 {
+        let age = (Local::now() - self.last_visit).num_days();
+        let weight = match age {
+            i64::MIN..=4 => 100,
+            5..=14 => 70,
+            15..=31 => 50,
+            32..=90 => 30,
+            _ => 10,
+        };
+        u64::from(self.visits) * weight
+    }
+}
+
+/// History files written by older versions of Eva stored only the time of
+/// the last visit for each url
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Entry(Entry),
+    Timestamp(DateTime<Local>),
+}
+
+impl From<StoredEntry> for Entry {
+    fn from(stored: StoredEntry) -> Self {
+        match stored {
+            StoredEntry::Entry(e) => e,
+            StoredEntry::Timestamp(date) => Self {
+                title: None,
+                visits: 1,
+                first_visit: date,
+                last_visit: date,
+            },
+        }
+    }
+}
+
+fn deserialize_items<'de, D>(deserializer: D) -> Result<HashMap<String, Entry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let items: HashMap<String, StoredEntry> = HashMap::deserialize(deserializer)?;
+    Ok(items.into_iter().map(|(k, v)| (k, v.into())).collect())
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct History {
-    items: HashMap<String, chrono::DateTime<Local>>,
+    #[serde(deserialize_with = "deserialize_items")]
+    items: HashMap<String, Entry>,
+    /// Changes which have not yet been written to the log
+    #[serde(skip)]
+    pending: Vec<Record>,
+    /// The number of lines in the log on disk
+    #[serde(skip)]
+    log_lines: usize,
+    /// The length in bytes of the log which has been read or written here.
+    /// `None` if not known, in which case the log is replayed from the start
+    /// when looking for changes made by another instance.
+    #[serde(skip)]
+    log_len: Option<u64>,
+    /// The first line of the log when it was last read or written here. Each
+    /// compaction starts the log with a new generation line, so if the first
+    /// line has changed the log has been rewritten by another instance.
+    #[serde(skip)]
+    generation: Option<String>,
+    /// Set when entries have been removed by the user, so that the next save
+    /// rewrites the log rather than appending to it
+    #[serde(skip)]
+    needs_compaction: bool,
+    /// The urls removed by the user since the log was last rewritten, which
+    /// must stay removed if the log is replayed
+    #[serde(skip)]
+    forgotten: Vec<String>,
+    /// How long and how many entries are kept, applied when the history is
+    /// loaded, compacted or the preferences change
+    #[serde(skip)]
+    retention: config::History,
 }
 
 impl History {
-    pub fn append(&mut self, url: &str) {
-        let _old = self.items.insert(String::from(url), Local::now());
+    /// Creates an empty history which keeps entries according to `retention`
+    #[must_use]
+    pub fn new(retention: &config::History) -> Self {
+        Self {
+            retention: retention.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Records a visit to `url`, creating a new entry if the url has not been
+    /// visited before
+    pub fn append(&mut self, url: &str, title: Option<&str>) {
+        self.record(Record::Visit {
+            url: String::from(url),
+            title: title.map(String::from),
+            time: Local::now(),
+        });
+    }
+
+    /// Applies `record` and queues it to be appended to the log
+    fn record(&mut self, record: Record) {
+        self.apply(record.clone());
+        self.pending.push(record);
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Visit { url, title, time } => {
+                if let Some(entry) = self.items.get_mut(&url) {
+                    entry.visits = entry.visits.saturating_add(1);
+                    entry.last_visit = time;
+                    if title.is_some() {
+                        entry.title = title;
+                    }
+                } else {
+                    let mut entry = Entry::new(title.as_deref());
+                    entry.first_visit = time;
+                    entry.last_visit = time;
+                    let _old = self.items.insert(url, entry);
+                }
+            }
+            Record::Entry { url, entry } => {
+                let _old = self.items.insert(url, entry);
+            }
+            Record::Remove(url) => {
+                let _old = self.items.remove(&url);
+            }
+        }
     }
 
     pub fn remove(&mut self, url: &str) {
-        let _old = self.items.remove(url);
+        if self.items.remove(url).is_some() {
+            self.forgotten.push(String::from(url));
+            self.needs_compaction = true;
+        }
     }
 
     pub fn clear(&mut self) {
-        self.items.clear();
+        self.forgotten
+            .extend(self.items.drain().map(|(url, _)| url));
+        self.needs_compaction = true;
     }
 
     #[must_use]
-    pub fn page(&self) -> String {
-        let mut page: String = String::from("# History\n");
-        for (url, date) in &self.items {
-            let _ = write!(page, "{date}\n=> {url}\n\n");
+    pub fn get(&self, url: &str) -> Option<&Entry> {
+        self.items.get(url)
+    }
+
+    /// Removes entries which have not been visited within `max_age` days, then
+    /// the least recently visited entries until at most `max_entries` remain.
+    /// A value of zero disables the corresponding limit.
+    pub fn prune(&mut self, max_age: u32, max_entries: u32) {
+        if max_age > 0 {
+            let cutoff = Local::now() - Duration::days(i64::from(max_age));
+            self.remove_older_than(cutoff);
+        }
+        let max_entries = max_entries as usize;
+        if max_entries > 0 && self.items.len() > max_entries {
+            let mut dates: Vec<DateTime<Local>> =
+                self.items.values().map(|e| e.last_visit).collect();
+            let (_, cutoff, _) = dates.select_nth_unstable_by_key(max_entries - 1, |d| Reverse(*d));
+            let cutoff = *cutoff;
+            self.remove_older_than(cutoff);
+        }
+    }
+
+    fn remove_older_than(&mut self, cutoff: DateTime<Local>) {
+        let expired: Vec<String> = self
+            .items
+            .iter()
+            .filter(|(_, entry)| entry.last_visit < cutoff)
+            .map(|(url, _)| url.clone())
+            .collect();
+        for url in expired {
+            self.record(Record::Remove(url));
+        }
+    }
+
+    /// Sets how long and how many entries are kept, according to the
+    /// `[history]` section of the config, and prunes the history to match
+    pub fn set_retention(&mut self, retention: &config::History) {
+        self.retention = retention.clone();
+        self.prune(retention.max_age, retention.max_entries);
+    }
+
+    /// Returns up to `n` entries, ranked by frecency
+    #[must_use]
+    pub fn top(&self, n: usize) -> Vec<(&str, &Entry)> {
+        let mut items: Vec<(&str, &Entry)> =
+            self.items.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        items.sort_by_key(|(_, e)| Reverse(e.frecency()));
+        items.truncate(n);
+        items
+    }
+
+    /// Returns every entry whose url begins with `prefix`, ranked by
+    /// frecency. The scheme may be omitted from `prefix`, so that `gemini.ci`
+    /// will match `gemini://gemini.circumlunar.space/`.
+    #[must_use]
+    pub fn matching(&self, prefix: &str) -> Vec<(&str, &Entry)> {
+        let mut items: Vec<(&str, &Entry)> = self
+            .items
+            .iter()
+            .filter(|(url, _)| {
+                url.starts_with(prefix)
+                    || url
+                        .split_once("://")
+                        .map_or(false, |(_, rest)| rest.starts_with(prefix))
+            })
+            .map(|(k, v)| (k.as_str(), v))
+            .collect();
+        items.sort_by_key(|(_, e)| Reverse(e.frecency()));
+        items
+    }
+
+    /// Removes every entry whose url points to `host`
+    pub fn remove_host(&mut self, host: &str) {
+        let len = self.items.len();
+        let forgotten = &mut self.forgotten;
+        self.items.retain(|url, _| match Url::parse(url) {
+            Ok(u) if u.host_str() == Some(host) => {
+                forgotten.push(url.clone());
+                false
+            }
+            _ => true,
+        });
+        if self.items.len() != len {
+            self.needs_compaction = true;
         }
-        page
     }
 
+    /// Returns the heading under which an entry visited on `date` is grouped
+    fn group(date: &DateTime<Local>, today: NaiveDate) -> String {
+        let days = (today - date.date_naive()).num_days();
+        match days {
+            0 => String::from("Today"),
+            1 => String::from("Yesterday"),
+            2..=6 => String::from("This week"),
+            _ => date.format("%B %Y").to_string(),
+        }
+    }
+
+    /// Renders the history as a gemtext page, newest entries first. If `query`
+    /// is given only those urls containing it are shown. Pages are numbered
+    /// starting from 1.
+    #[must_use]
+    pub fn page(&self, query: Option<&str>, page: usize) -> String {
+        let query = query.map(str::to_lowercase);
+        let mut items: Vec<(&String, &Entry)> = self
+            .items
+            .iter()
+            .filter(|(url, _)| match &query {
+                Some(q) => url.to_lowercase().contains(q.as_str()),
+                None => true,
+            })
+            .collect();
+        items.sort_by_key(|(_, e)| Reverse(e.last_visit));
+        let pages = (items.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+        let page = page.clamp(1, pages.max(1));
+        // Carried by the forget links so that the same page is shown again
+        let position = match &query {
+            Some(q) => format!("&query={}&page={page}", urlencoding::encode(q)),
+            None => format!("&page={page}"),
+        };
+        let mut gmi = String::from(
+            "# History\n\n=> eva://history/search Search history\n\
+            => eva://history/export Export history\n\
+            => eva://history/import Import history\n",
+        );
+        if let Some(q) = &query {
+            let _ = writeln!(
+                gmi,
+                "=> eva://history Show all history\n\n{} results for \"{q}\"",
+                items.len()
+            );
+        }
+        let today = Local::now().date_naive();
+        let mut current_group = String::new();
+        for (url, entry) in items.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+            let group = Self::group(&entry.last_visit, today);
+            if group != current_group {
+                let _ = writeln!(gmi, "\n## {group}");
+                current_group = group;
+            }
+            let _ = write!(
+                gmi,
+                "\n### {}\n=> {url} {}\nVisited {} time{}\n=> eva://history/forget?url={}{position} Forget this page\n",
+                entry.last_visit.format("%Y-%m-%d %H:%M"),
+                entry.title.as_deref().unwrap_or(url),
+                entry.visits,
+                if entry.visits == 1 { "" } else { "s" },
+                urlencoding::encode(url),
+            );
+            if let Ok(u) = Url::parse(url) {
+                if let Some(host) = u.host_str() {
+                    let _ = writeln!(
+                        gmi,
+                        "=> eva://history/forget_host?host={}{position} Forget all pages from {host}",
+                        urlencoding::encode(host),
+                    );
+                }
+            }
+        }
+        if pages > 1 {
+            let _ = writeln!(gmi, "\n--\nPage {page} of {pages}");
+            if page > 1 {
+                let _ = writeln!(
+                    gmi,
+                    "=> {} Previous page",
+                    page_url(query.as_deref(), page - 1)
+                );
+            }
+            if page < pages {
+                let _ = writeln!(gmi, "=> {} Next page", page_url(query.as_deref(), page + 1));
+            }
+        }
+        gmi
+    }
+
+    /// Exports every entry in `format`, newest first
     /// # Errors
-    /// Returns an error if unable to get the data directory path, unable to
-    /// create the data directory, unable to serialize toml or unable to write
-    /// the toml to disk
-    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+    /// Returns an error if the history can not be written in `format`
+    pub fn export(&self, format: Format) -> Result<String, Box<dyn Error>> {
+        let mut items: Vec<(&String, &Entry)> = self.items.iter().collect();
+        items.sort_by_key(|(_, e)| Reverse(e.last_visit));
+        format::export(&items, format)
+    }
+
+    /// Merges the entries read from `text` into the history, returning how
+    /// many were read. Urls which are already in the history keep the larger
+    /// visit count and the widest range of dates, so importing the same file
+    /// twice has no further effect.
+    /// # Errors
+    /// Returns an error if `text` can not be parsed as `format`
+    pub fn import(&mut self, text: &str, format: Format) -> Result<usize, Box<dyn Error>> {
+        let entries = format::import(text, format)?;
+        let count = entries.len();
+        for (url, mut entry) in entries {
+            if let Some(old) = self.items.get(&url) {
+                entry.visits = entry.visits.max(old.visits);
+                entry.first_visit = entry.first_visit.min(old.first_visit);
+                entry.last_visit = entry.last_visit.max(old.last_visit);
+                if old.title.is_some() {
+                    entry.title.clone_from(&old.title);
+                }
+            }
+            self.record(Record::Entry { url, entry });
+        }
+        Ok(count)
+    }
+
+    /// Appends any changes to the log on disk. The log is rewritten instead
+    /// if it has grown too large or if entries have been removed by the user.
+    /// # Errors
+    /// Returns an error if unable to create the data directory or unable to
+    /// write to the log
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        // Creating the log would leave a history.toml which could not be
+        // read behind for good
+        storage::check_writable(&get_history_source())?;
+        if let Err(e) = self.sync() {
+            eprintln!("{}", e);
+        }
+        let histlog = get_history_log();
+        if self.needs_compaction || !histlog.exists() || self.log_too_long() {
+            return self.compact();
+        }
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for record in &self.pending {
+            let _ = writeln!(lines, "{record}");
+        }
+        let mut file = OpenOptions::new().append(true).open(histlog)?;
+        file.write_all(lines.as_bytes())?;
+        // If another instance appended to the log in the meantime, the offset
+        // of the end of what has been read here is lost, so it is replayed
+        let expected = self.log_len.map(|len| len + lines.len() as u64);
+        self.log_len = expected.filter(|len| file.metadata().map_or(false, |m| m.len() == *len));
+        self.log_lines += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Whether the log has grown enough beyond one line per entry that it
+    /// should be compacted
+    fn log_too_long(&self) -> bool {
+        self.log_lines + self.pending.len() > self.items.len() * 2 + COMPACT_SLACK
+    }
+
+    /// Picks up the records which another instance of Eva has added to the
+    /// log since it was last read or written here. If the log has been
+    /// rewritten, it is replayed from the start, keeping any changes made
+    /// here which are not yet on disk. Returns `true` if anything was read.
+    /// # Errors
+    /// Returns an error if unable to read the log
+    pub fn sync(&mut self) -> Result<bool, Box<dyn Error>> {
+        self.sync_log(&get_history_log())
+    }
+
+    fn sync_log(&mut self, histlog: &Path) -> Result<bool, Box<dyn Error>> {
+        let len = match std::fs::metadata(histlog) {
+            Ok(m) => m.len(),
+            Err(_) => return Ok(false),
+        };
+        let mut file = File::open(histlog)?;
+        let mut first = String::new();
+        BufReader::new(&mut file).read_line(&mut first)?;
+        // A rewritten log may be longer than the one read here, so its length
+        // alone does not tell it apart from one which has been appended to
+        let rewritten = !first.is_empty() && self.generation.as_deref() != Some(first.trim_end());
+        let start = match self.log_len {
+            _ if rewritten => 0,
+            Some(known) if known == len => return Ok(false),
+            Some(known) if known < len => known,
+            _ => 0,
+        };
+        file.seek(SeekFrom::Start(start))?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        // A line which is still being written is left for next time
+        let end = bytes.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        let text = String::from_utf8_lossy(&bytes[..end]);
+        if start == 0 {
+            self.items.clear();
+            self.log_lines = 0;
+            self.generation = text.lines().next().map(String::from);
+        }
+        for line in text.lines() {
+            self.log_lines += 1;
+            if let Some(record) = Record::parse(line) {
+                self.apply(record);
+            }
+        }
+        if start == 0 {
+            for url in &self.forgotten {
+                let _old = self.items.remove(url);
+            }
+            for record in self.pending.clone() {
+                self.apply(record);
+            }
+        }
+        self.log_len = Some(start + end as u64);
+        Ok(end > 0 || start == 0)
+    }
+
+    /// Prunes the history, then rewrites the log with a single record for
+    /// each entry
+    /// # Errors
+    /// Returns an error if unable to create the data directory or unable to
+    /// write the log
+    pub fn compact(&mut self) -> Result<(), Box<dyn Error>> {
+        self.prune(self.retention.max_age, self.retention.max_entries);
         let datadir = get_data_dir();
-        let histfile = get_history_file();
         if !datadir.exists() {
-            let dd = match datadir.to_str() {
-                Some(d) => d,
-                None => return Err(String::from("Empty data directory path").into()),
-            };
-            std::fs::create_dir(&dd)?;
+            std::fs::create_dir_all(&datadir)?;
+        }
+        let generation = record::format_generation();
+        let mut lines = format!("{generation}\n");
+        for (url, entry) in &self.items {
+            lines.push_str(&record::format_entry(url, entry));
+            lines.push('\n');
         }
-        let toml_string = toml::to_string(self)?;
-        std::fs::write(histfile, toml_string)?;
+        let len = lines.len() as u64;
+        // Backups would keep the pages which the user asked to forget
+        if self.forgotten.is_empty() {
+            storage::write(&get_history_log(), lines)?;
+        } else {
+            storage::write_forgetting(&get_history_log(), lines)?;
+        }
+        self.log_lines = self.items.len() + 1;
+        self.log_len = Some(len);
+        self.generation = Some(generation);
+        self.pending.clear();
+        self.needs_compaction = false;
+        self.forgotten.clear();
         Ok(())
     }
 
+    /// Replays the history log, keeping entries according to `retention`. If
+    /// there is no log but there is a history.toml file from an older version
+    /// of Eva, that is read instead and the log is created from it by the next
+    /// save. Nothing is written here, so that the log is only ever compacted
+    /// with the retention limits already in place.
     /// # Errors
     /// Returns an error if unable to read the history from disk, or unable to
     /// deserialize toml
-    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+    pub fn from_file(retention: &config::History) -> Result<Option<Self>, Box<dyn Error>> {
+        let histlog = get_history_log();
+        if histlog.exists() {
+            let log = std::fs::read_to_string(histlog)?;
+            return Ok(Some(Self::from_log(&log, retention)));
+        }
         let histfile = get_history_file();
-        let histfile = if histfile.exists() {
-            std::fs::read_to_string(histfile)?
-        } else {
+        if !histfile.exists() {
             return Ok(None);
-        };
-        let history = toml::from_str(&histfile)?;
+        }
+        let mut history: Self = toml::from_str(&std::fs::read_to_string(histfile)?)?;
+        history.set_retention(retention);
+        history.needs_compaction = true;
         Ok(Some(history))
     }
+
+    /// Replays the records of `log`, then prunes the entries according to
+    /// `retention`
+    fn from_log(log: &str, retention: &config::History) -> Self {
+        let mut history = Self {
+            log_len: Some(log.len() as u64),
+            generation: log.lines().next().map(String::from),
+            ..Self::default()
+        };
+        for line in log.lines() {
+            history.log_lines += 1;
+            if let Some(record) = Record::parse(line) {
+                history.apply(record);
+            }
+        }
+        history.set_retention(retention);
+        history.needs_compaction = history.log_too_long();
+        history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A log with `n` entries, the newest of which was visited `age` days ago
+    fn old_log(n: usize, age: i64) -> String {
+        let mut log = String::new();
+        for i in 0..n {
+            let time =
+                Local::now() - Duration::days(age) - Duration::minutes(i64::try_from(i).unwrap());
+            let entry = Entry {
+                title: Some(format!("Page {i}")),
+                visits: 2,
+                first_visit: time,
+                last_visit: time,
+            };
+            log.push_str(&record::format_entry(
+                &format!("gemini://example.org/{i}"),
+                &entry,
+            ));
+            log.push('\n');
+        }
+        log
+    }
+
+    #[test]
+    fn unlimited_retention_keeps_everything() {
+        let retention = config::History {
+            max_age: 0,
+            max_entries: 0,
+            ..config::History::default()
+        };
+        let mut history = History::from_log(&old_log(11_000, 730), &retention);
+        assert_eq!(history.items.len(), 11_000);
+        assert!(history.pending.is_empty());
+        // Compaction prunes with the same limits
+        history.prune(history.retention.max_age, history.retention.max_entries);
+        assert_eq!(history.items.len(), 11_000);
+        let entry = history.get("gemini://example.org/10999").unwrap();
+        assert_eq!(entry.title().as_deref(), Some("Page 10999"));
+        assert_eq!(entry.visits(), 2);
+    }
+
+    #[test]
+    fn old_history_file() {
+        // Eva used to store only the time of the last visit, and later full
+        // entries, so a history.toml may hold both
+        let toml = r#"
+            [items]
+            "gemini://old.example.org/" = "2022-11-05T10:30:00.123456789+01:00"
+
+            [items."gemini://new.example.org/"]
+            title = "New page"
+            visits = 3
+            first_visit = "2022-11-01T08:00:00+01:00"
+            last_visit = "2022-11-06T09:15:00+01:00"
+        "#;
+        let history: History = toml::from_str(toml).unwrap();
+        assert_eq!(history.items.len(), 2);
+        let old = history.get("gemini://old.example.org/").unwrap();
+        assert_eq!(old.title(), None);
+        assert_eq!(old.visits(), 1);
+        assert_eq!(
+            old.last_visit(),
+            DateTime::parse_from_rfc3339("2022-11-05T10:30:00.123456789+01:00").unwrap()
+        );
+        assert_eq!(old.first_visit(), old.last_visit());
+        let new = history.get("gemini://new.example.org/").unwrap();
+        assert_eq!(new.title().as_deref(), Some("New page"));
+        assert_eq!(new.visits(), 3);
+        assert_eq!(
+            new.first_visit(),
+            DateTime::parse_from_rfc3339("2022-11-01T08:00:00+01:00").unwrap()
+        );
+        assert_eq!(
+            new.last_visit(),
+            DateTime::parse_from_rfc3339("2022-11-06T09:15:00+01:00").unwrap()
+        );
+    }
+
+    #[test]
+    fn links_keep_query_and_page() {
+        let history = History::from_log(&old_log(120, 0), &config::History::default());
+        let gmi = history.page(Some("Example"), 2);
+        assert!(gmi.contains(
+            "=> eva://history/forget?url=gemini%3A%2F%2Fexample.org%2F50&query=example&page=2 "
+        ));
+        assert!(gmi.contains("=> eva://history/forget_host?host=example.org&query=example&page=2 "));
+        assert!(gmi.contains("=> eva://history?example Previous page"));
+        assert!(gmi.contains("=> eva://history/page/3?example Next page"));
+        assert_eq!(page_url(None, 1), "eva://history");
+        assert_eq!(page_url(Some("a b"), 2), "eva://history/page/2?a%20b");
+    }
+
+    #[test]
+    fn retention_applies_on_load() {
+        let retention = config::History {
+            max_age: 365,
+            max_entries: 5,
+            ..config::History::default()
+        };
+        let mut log = old_log(3, 730);
+        log.push_str(&old_log(10, 1));
+        let history = History::from_log(&log, &retention);
+        assert_eq!(history.items.len(), 5);
+        // The removals are only written by the next save
+        assert_eq!(history.pending.len(), 5);
+    }
+
+    #[test]
+    fn long_log_is_compacted_by_next_save() {
+        let mut log = String::new();
+        for _ in 0..COMPACT_SLACK * 2 {
+            let _ = writeln!(
+                log,
+                "{}",
+                Record::Visit {
+                    url: String::from("gemini://example.org/"),
+                    title: None,
+                    time: Local::now(),
+                }
+            );
+        }
+        let history = History::from_log(&log, &config::History::default());
+        assert_eq!(history.items.len(), 1);
+        assert!(history.needs_compaction);
+    }
+
+    #[test]
+    fn sync_replays_rewritten_log() {
+        let path = std::env::temp_dir().join(format!("eva-test-{}.log", std::process::id()));
+        let entry = |url: &str, title: &str| {
+            let mut entry = Entry::new(Some(title));
+            entry.visits = 3;
+            format!("{}\n", record::format_entry(url, &entry))
+        };
+        let old = format!(
+            "{}\n{}",
+            record::format_generation(),
+            entry("gemini://a.example.org/", "A")
+        );
+        std::fs::write(&path, &old).unwrap();
+        let mut history = History::from_log(&old, &config::History::default());
+        history.append("gemini://c.example.org/", None);
+        // Another instance compacts the log, which ends up longer than before
+        let new = format!(
+            "{}\n{}{}",
+            record::format_generation(),
+            entry("gemini://b.example.org/", "A much longer title than before"),
+            entry("gemini://a.example.org/", "A"),
+        );
+        assert!(new.len() > old.len());
+        std::fs::write(&path, &new).unwrap();
+        assert!(history.sync_log(&path).unwrap());
+        assert_eq!(history.items.len(), 3);
+        assert_eq!(history.get("gemini://a.example.org/").unwrap().visits(), 3);
+        assert_eq!(history.get("gemini://c.example.org/").unwrap().visits(), 1);
+        // Then appends to it, which is read without replaying the rest
+        let visit = Record::Visit {
+            url: String::from("gemini://a.example.org/"),
+            title: None,
+            time: Local::now(),
+        };
+        std::fs::write(&path, format!("{new}{visit}\n")).unwrap();
+        assert!(history.sync_log(&path).unwrap());
+        assert_eq!(history.get("gemini://a.example.org/").unwrap().visits(), 4);
+        assert!(!history.sync_log(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,113 @@
+//! The history is stored on disk as an append-only log, one record per line,
+//! with the fields of each record separated by tabs. Recording a visit only
+//! appends a single line, while the log is periodically compacted down to one
+//! `entry` record for each url. Pages which the user removes by hand are not
+//! logged, instead the whole log is rewritten so that nothing forgotten is
+//! left on disk. A compacted log starts with a `generation` line which is
+//! different each time, so that other instances notice it was rewritten.
+use {
+    super::Entry,
+    chrono::prelude::*,
+    std::fmt::{self, Display},
+};
+
+#[derive(Clone, Debug)]
+pub(super) enum Record {
+    /// A single visit to a url, with the page title if known
+    Visit {
+        url: String,
+        title: Option<String>,
+        time: DateTime<Local>,
+    },
+    /// The complete state of a url's entry, as written by compaction
+    Entry { url: String, entry: Entry },
+    /// An entry removed because it fell outside the retention limits
+    Remove(String),
+}
+
+/// Tabs and newlines would break the log's format, so they are replaced
+fn clean(field: &str) -> String {
+    field.replace(['\t', '\n', '\r'], " ")
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|t| t.with_timezone(&Local))
+}
+
+fn parse_title(title: Option<&str>) -> Option<String> {
+    title.filter(|t| !t.is_empty()).map(String::from)
+}
+
+/// Formats the `entry` record for `url` without first building a `Record`
+pub(super) fn format_entry(url: &str, entry: &Entry) -> String {
+    format!(
+        "entry\t{}\t{}\t{}\t{}\t{}",
+        entry.first_visit.to_rfc3339(),
+        entry.last_visit.to_rfc3339(),
+        entry.visits,
+        clean(url),
+        clean(entry.title.as_deref().unwrap_or("")),
+    )
+}
+
+/// Formats a new `generation` line for the start of a compacted log
+pub(super) fn format_generation() -> String {
+    let id: String = std::iter::repeat_with(fastrand::alphanumeric)
+        .take(8)
+        .collect();
+    format!("generation\t{}\t{id}", Local::now().to_rfc3339())
+}
+
+impl Record {
+    /// Parses a single line of the log. Returns `None` for a line which is
+    /// not understood, such as one which was only partly written.
+    pub(super) fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        match fields.next()? {
+            "visit" => {
+                let time = parse_time(fields.next()?)?;
+                let url = String::from(fields.next()?);
+                Some(Self::Visit {
+                    url,
+                    title: parse_title(fields.next()),
+                    time,
+                })
+            }
+            "entry" => {
+                let first_visit = parse_time(fields.next()?)?;
+                let last_visit = parse_time(fields.next()?)?;
+                let visits = fields.next()?.parse().ok()?;
+                let url = String::from(fields.next()?);
+                Some(Self::Entry {
+                    url,
+                    entry: Entry {
+                        title: parse_title(fields.next()),
+                        visits,
+                        first_visit,
+                        last_visit,
+                    },
+                })
+            }
+            "remove" => Some(Self::Remove(String::from(fields.next()?))),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Visit { url, title, time } => write!(
+                f,
+                "visit\t{}\t{}\t{}",
+                time.to_rfc3339(),
+                clean(url),
+                clean(title.as_deref().unwrap_or("")),
+            ),
+            Self::Entry { url, entry } => write!(f, "{}", format_entry(url, entry)),
+            Self::Remove(url) => write!(f, "remove\t{}", clean(url)),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use {
     crate::config::get_config_dir,
     serde::Deserialize,
-    std::{collections::HashMap, fs, path::PathBuf},
+    std::{collections::HashMap, error::Error, fs, path::PathBuf},
 };
 
 /// Returns the path to keys.toml
@@ -28,6 +28,8 @@ impl Keys {
         match action {
             "new_tab" => "<primary>T",
             "close_tab" => "<primary>W",
+            "reopen_closed_tab" => "<primary><Shift>T",
+            "reopen_closed_window" => "<primary><Shift><Alt>T",
             "next_tab" => "<primary>Page_Down",
             "prev_tab" => "<primary>Page_Up",
             "tab1" => "<Alt>1",
@@ -44,9 +46,14 @@ impl Keys {
             "go_previous" => "<Alt>Left",
             "go_next" => "<Alt>Right",
             "new_window" => "<primary>N",
+            "new_private_window" => "<primary><Shift>N",
             "open_bookmarks" => "<primary><Shift>O",
             "bookmark_page" => "<primary>D",
             "open_history" => "<primary>H",
+            "open_sessions" => "<primary><Shift>E",
+            "save_session" => "<primary><Alt>S",
+            "open_feeds" => "<primary><Shift>F",
+            "subscribe" => "<primary><Alt>F",
             "view_source" => "<primary>U",
             "save_page" => "<primary>S",
             "open_prefs" => "<primary><Shift>P",
@@ -56,24 +63,16 @@ impl Keys {
         }
     }
 
-    #[must_use]
-    pub fn from_file() -> Option<Self> {
+    /// # Errors
+    /// Returns an error if unable to read keys.toml or deserialize toml
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
         let keyfile = get_key_file();
         let keyfile = if keyfile.exists() {
-            match fs::read_to_string(keyfile) {
-                Ok(k) => k,
-                Err(_) => return None,
-            }
+            fs::read_to_string(keyfile)?
         } else {
-            return None;
-        };
-        let keys: Self = match toml::from_str(&keyfile) {
-            Ok(k) => k,
-            Err(e) => {
-                eprintln!("{}", e);
-                return None;
-            }
+            return Ok(None);
         };
-        Some(keys)
+        let keys: Self = toml::from_str(&keyfile)?;
+        Ok(Some(keys))
     }
 }
@@ -5,22 +5,61 @@ use {lazy_static::lazy_static, std::sync::Mutex};
 pub mod bookmarks;
 /// Handles getting the configuration data to and from disk
 pub mod config;
+/// Subscriptions to gemlogs and Atom feeds
+pub mod feeds;
 /// Starts the graphical interface
 mod gui;
 /// Handles history creation and deletion
 pub mod history;
 /// Handles loading keybindings
 pub mod keys;
+/// Saves and restores open windows and tabs
+pub mod session;
+/// Reading and writing user data files which may change while Eva runs
+pub mod storage;
 
 lazy_static! {
-    static ref CONFIG: Mutex<config::Config> =
-        Mutex::new(config::Config::from_file().unwrap_or_default());
+    static ref CONFIG: Mutex<config::Config> = Mutex::new(match config::Config::from_file() {
+        Ok(c) => c.unwrap_or_default(),
+        Err(e) => {
+            storage::load_failed(&config::get_config_file(), &e);
+            config::Config::default()
+        }
+    });
     static ref BOOKMARKS: Mutex<bookmarks::Bookmarks> =
         Mutex::new(match bookmarks::Bookmarks::from_file() {
             Ok(b) => b.unwrap_or_default(),
-            Err(_) => bookmarks::Bookmarks::default(),
+            Err(e) => {
+                storage::load_failed(&bookmarks::get_bookmarks_file(), &e);
+                bookmarks::Bookmarks::default()
+            }
         });
-    static ref SEARCH: gui::uri::Search = gui::uri::Search::load();
+    static ref HISTORY: Mutex<history::History> = {
+        let retention = CONFIG.lock().unwrap().history.clone();
+        Mutex::new(match history::History::from_file(&retention) {
+            Ok(h) => h.unwrap_or_else(|| history::History::new(&retention)),
+            Err(e) => {
+                storage::load_failed(&history::get_history_source(), &e);
+                history::History::new(&retention)
+            }
+        })
+    };
+    static ref SESSIONS: Mutex<session::NamedSessions> =
+        Mutex::new(match session::NamedSessions::from_file() {
+            Ok(s) => s.unwrap_or_default(),
+            Err(e) => {
+                storage::load_failed(&session::get_named_sessions_file(), &e);
+                session::NamedSessions::default()
+            }
+        });
+    static ref FEEDS: Mutex<feeds::Feeds> = Mutex::new(match feeds::Feeds::from_file() {
+        Ok(f) => f.unwrap_or_default(),
+        Err(e) => {
+            storage::load_failed(&feeds::get_feeds_file(), &e);
+            feeds::Feeds::default()
+        }
+    });
+    static ref SEARCH: Mutex<gui::uri::Search> = Mutex::new(gui::uri::Search::load());
 }
 
 fn main() {
@@ -0,0 +1,162 @@
+#![warn(clippy::all, clippy::pedantic)]
+use {
+    crate::{gui::tab::Navigation, history::get_data_dir, storage},
+    chrono::prelude::*,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, error::Error, fmt::Write, path::PathBuf},
+};
+
+#[must_use]
+pub fn get_session_file() -> PathBuf {
+    let mut session = get_data_dir();
+    session.push("session.toml");
+    session
+}
+
+#[must_use]
+pub fn get_named_sessions_file() -> PathBuf {
+    let mut sessions = get_data_dir();
+    sessions.push("sessions.toml");
+    sessions
+}
+
+/// The saved state of a single browser window
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Window {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    /// The index of the tab which was being displayed
+    pub active: u32,
+    pub tabs: Vec<Navigation>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Session {
+    pub windows: Vec<Window>,
+}
+
+impl Session {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.windows.iter().all(|w| w.tabs.is_empty())
+    }
+
+    /// # Errors
+    /// Returns an error if unable to create the data directory, unable to
+    /// serialize toml or unable to write the toml to disk
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let datadir = get_data_dir();
+        if !datadir.exists() {
+            std::fs::create_dir_all(&datadir)?;
+        }
+        let toml_string = toml::to_string(self)?;
+        storage::write(&get_session_file(), toml_string)?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns an error if unable to read the session file or deserialize toml
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        let file = get_session_file();
+        let file = if file.exists() {
+            std::fs::read_to_string(file)?
+        } else {
+            return Ok(None);
+        };
+        let session = toml::from_str(&file)?;
+        Ok(Some(session))
+    }
+}
+
+/// A window's tabs saved under a name chosen by the user
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NamedSession {
+    pub saved: DateTime<Local>,
+    pub window: Window,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NamedSessions {
+    /// key is the session name
+    pub all: HashMap<String, NamedSession>,
+}
+
+impl NamedSessions {
+    /// Saves `window` as `name`, replacing any session with the same name
+    pub fn insert(&mut self, name: &str, window: Window) {
+        let _old = self.all.insert(
+            String::from(name),
+            NamedSession {
+                saved: Local::now(),
+                window,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        let _old = self.all.remove(name);
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&NamedSession> {
+        self.all.get(name)
+    }
+
+    #[must_use]
+    pub fn to_gmi(&self) -> String {
+        let mut page = String::from(
+            "# Sessions\n\n=> eva://sessions/save Save the current window as a session\n",
+        );
+        let mut names: Vec<&String> = self.all.keys().collect();
+        names.sort();
+        for name in names {
+            let session = &self.all[name];
+            let tabs = session.window.tabs.len();
+            let encoded = urlencoding::encode(name);
+            let _ = write!(
+                page,
+                "\n### {name}\n{tabs} tab{}, saved {}\n",
+                if tabs == 1 { "" } else { "s" },
+                session.saved.format("%Y-%m-%d %H:%M"),
+            );
+            for nav in &session.window.tabs {
+                if let Some(uri) = nav.current() {
+                    let _ = writeln!(page, "* {uri}");
+                }
+            }
+            let _ = writeln!(
+                page,
+                "=> eva://sessions/open?{encoded} Open in a new window\n\
+                => eva://sessions/delete?{encoded} Delete this session",
+            );
+        }
+        page
+    }
+
+    /// # Errors
+    /// Returns an error if unable to create the data directory, unable to
+    /// serialize toml or unable to write the toml to disk
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let datadir = get_data_dir();
+        if !datadir.exists() {
+            std::fs::create_dir_all(&datadir)?;
+        }
+        let toml_string = toml::to_string(self)?;
+        storage::write(&get_named_sessions_file(), toml_string)?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns an error if unable to read the sessions file or deserialize toml
+    pub fn from_file() -> Result<Option<Self>, Box<dyn Error>> {
+        let file = get_named_sessions_file();
+        let file = if file.exists() {
+            std::fs::read_to_string(file)?
+        } else {
+            return Ok(None);
+        };
+        let sessions = toml::from_str(&file)?;
+        Ok(Some(sessions))
+    }
+}
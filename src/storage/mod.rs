@@ -0,0 +1,269 @@
+#![warn(clippy::all, clippy::pedantic)]
+//! Reading and writing the files which hold the user's data. These may also
+//! be changed by another instance of Eva, or by hand, while Eva is running,
+//! so changes are merged rather than one copy overwriting the other.
+//!
+//! Files are written to a temporary file which then replaces the original,
+//! so that a crash or a full disk cannot leave a file half written, and
+//! older copies are kept as backups. A file which cannot be read is not
+//! written to until the user has chosen to restore a backup or start over.
+use {
+    lazy_static::lazy_static,
+    std::{
+        collections::BTreeSet,
+        error::Error,
+        fmt::Display,
+        fs::{self, File},
+        io::{self, Write},
+        path::{Path, PathBuf},
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    },
+    toml::{value::Table, Value},
+};
+
+/// How many backups are kept of each file
+pub const BACKUPS: usize = 3;
+
+/// How long to wait after making a backup of a file before making another,
+/// so that frequent saves do not push every older copy out
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// Files which could not be read, and so must not be overwritten
+    static ref FAILED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    /// Files which could not be read, which the user has not been told about
+    static ref UNREPORTED: Mutex<Vec<Failure>> = Mutex::new(Vec::new());
+}
+
+/// A file which could not be read when Eva started
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Appends `suffix` to the file name of `path`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// The path of the `n`th most recent backup of `path`, counting from 1
+#[must_use]
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{n}"))
+}
+
+/// The path to which a file which could not be read is moved, so that it is
+/// not lost when the user starts over
+#[must_use]
+pub fn broken_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".broken")
+}
+
+/// The existing backups of `path` with the time each was made, newest first
+#[must_use]
+pub fn backups(path: &Path) -> Vec<(PathBuf, SystemTime)> {
+    (1..=BACKUPS)
+        .map(|n| backup_path(path, n))
+        .filter_map(|p| {
+            let modified = fs::metadata(&p).and_then(|m| m.modified()).ok()?;
+            Some((p, modified))
+        })
+        .collect()
+}
+
+/// Shifts the older backups of `path` along, dropping the oldest, and copies
+/// `path` in as the newest, unless the newest is still recent
+fn backup(path: &Path) -> io::Result<()> {
+    let newest = backup_path(path, 1);
+    if let Ok(modified) = fs::metadata(&newest).and_then(|m| m.modified()) {
+        if modified
+            .elapsed()
+            .map_or(false, |age| age < BACKUP_INTERVAL)
+        {
+            return Ok(());
+        }
+    }
+    for n in (1..BACKUPS).rev() {
+        let older = backup_path(path, n);
+        if older.exists() {
+            fs::rename(older, backup_path(path, n + 1))?;
+        }
+    }
+    let _len = fs::copy(path, newest)?;
+    Ok(())
+}
+
+/// Replaces the contents of `path` without ever leaving it half written
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+/// Refuses to write to `path` if it could not be read when Eva started
+/// # Errors
+/// Returns an error if `path` could not be read and the user has not yet
+/// decided what to do with it
+/// # Panics
+/// Panics if the mutex holding the failed files is poisoned
+pub fn check_writable(path: &Path) -> io::Result<()> {
+    if FAILED.lock().unwrap().iter().any(|p| p == path) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} was not saved, as it could not be read and may still be recovered",
+                path.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes `contents` to the file at `path`, keeping a backup of what was
+/// there before
+/// # Errors
+/// Returns an error if `path` could not be read when Eva started and the
+/// user has not yet decided what to do with it, or if unable to write the
+/// file
+/// # Panics
+/// Panics if the mutex holding the failed files is poisoned
+pub fn write<C: AsRef<[u8]>>(path: &Path, contents: C) -> io::Result<()> {
+    check_writable(path)?;
+    if path.exists() {
+        if let Err(e) = backup(path) {
+            eprintln!("Unable to back up {}: {}", path.display(), e);
+        }
+    }
+    replace(path, contents.as_ref())
+}
+
+/// Writes `contents` to the file at `path` like [`write`], but deletes the
+/// backups instead of making one, so that nothing which was removed from
+/// the file is left on disk
+/// # Errors
+/// Returns an error if `path` could not be read when Eva started and the
+/// user has not yet decided what to do with it, or if unable to write the
+/// file or delete a backup
+/// # Panics
+/// Panics if the mutex holding the failed files is poisoned
+pub fn write_forgetting<C: AsRef<[u8]>>(path: &Path, contents: C) -> io::Result<()> {
+    check_writable(path)?;
+    replace(path, contents.as_ref())?;
+    for n in 1..=BACKUPS {
+        let backup = backup_path(path, n);
+        if backup.exists() {
+            fs::remove_file(backup)?;
+        }
+    }
+    Ok(())
+}
+
+/// Records that the file at `path` could not be read, so that it is not
+/// overwritten and the user can be asked about it
+/// # Panics
+/// Panics if the mutex holding the failed files is poisoned
+pub fn load_failed(path: &Path, error: &dyn Display) {
+    eprintln!("Unable to read {}: {}", path.display(), error);
+    FAILED.lock().unwrap().push(path.to_path_buf());
+    UNREPORTED.lock().unwrap().push(Failure {
+        path: path.to_path_buf(),
+        error: error.to_string(),
+    });
+}
+
+/// Returns the files which could not be read that the user has not yet been
+/// told about
+/// # Panics
+/// Panics if the mutex holding the failures is poisoned
+#[must_use]
+pub fn take_failures() -> Vec<Failure> {
+    std::mem::take(&mut *UNREPORTED.lock().unwrap())
+}
+
+/// Moves a file which could not be read out of the way, so that it can be
+/// written again. Returns where it was moved to, if it exists.
+/// # Errors
+/// Returns an error if unable to move the file
+/// # Panics
+/// Panics if the mutex holding the failed files is poisoned
+pub fn set_aside(path: &Path) -> io::Result<Option<PathBuf>> {
+    let broken = if path.exists() {
+        let broken = broken_path(path);
+        fs::rename(path, &broken)?;
+        Some(broken)
+    } else {
+        None
+    };
+    FAILED.lock().unwrap().retain(|p| p != path);
+    Ok(broken)
+}
+
+/// Copies `backup` over the file at `path`
+/// # Errors
+/// Returns an error if unable to read the backup or write the file
+pub fn restore(path: &Path, backup: &Path) -> io::Result<()> {
+    replace(path, &fs::read(backup)?)
+}
+
+/// Reads and parses the toml file at `path`, returning `None` if it does not
+/// exist
+/// # Errors
+/// Returns an error if unable to read the file or parse toml
+pub fn read_value(path: &Path) -> Result<Option<Value>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    Ok(Some(toml::from_str(&text)?))
+}
+
+/// Merges the changes which were made here (`ours`) with those which were
+/// made elsewhere (`theirs`), where both started from `base`, the version
+/// which was last read or written here. Tables are merged key by key. Where
+/// both sides changed the same value, ours wins.
+#[must_use]
+pub fn merge(base: Option<&Value>, ours: &Value, theirs: &Value) -> Value {
+    merge_value(base, Some(ours), Some(theirs)).unwrap_or_else(|| ours.clone())
+}
+
+/// Merges a single value, which is `None` where it is missing or removed
+fn merge_value(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+) -> Option<Value> {
+    if ours == base {
+        return theirs.cloned();
+    }
+    if theirs == base || theirs == ours {
+        return ours.cloned();
+    }
+    match (ours, theirs) {
+        (Some(Value::Table(ours)), Some(Value::Table(theirs))) => {
+            let empty = Table::new();
+            let base = match base {
+                Some(Value::Table(base)) => base,
+                _ => &empty,
+            };
+            let keys: BTreeSet<&String> = base
+                .keys()
+                .chain(ours.keys())
+                .chain(theirs.keys())
+                .collect();
+            let mut merged = Table::new();
+            for key in keys {
+                if let Some(value) = merge_value(base.get(key), ours.get(key), theirs.get(key)) {
+                    let _old = merged.insert(key.clone(), value);
+                }
+            }
+            Some(Value::Table(merged))
+        }
+        _ => ours.cloned(),
+    }
+}